@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use glam::{IVec3, Vec3};
+use serde::Deserialize;
+
+use crate::backend::SimulationBackend;
+
+// * Per-particle scalar the fluid surface can be coloured by
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParticleAttribute {
+    Speed,
+    Density,
+    #[default]
+    Pressure,
+    // * Magnitude of the curl of the velocity field
+    Vorticity,
+    // * Index of the particle in the scene, to follow particles around
+    Id,
+}
+
+impl ParticleAttribute {
+    pub const ALL: [ParticleAttribute; 5] = [
+        ParticleAttribute::Speed,
+        ParticleAttribute::Density,
+        ParticleAttribute::Pressure,
+        ParticleAttribute::Vorticity,
+        ParticleAttribute::Id,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ParticleAttribute::Speed => "speed",
+            ParticleAttribute::Density => "density",
+            ParticleAttribute::Pressure => "pressure",
+            ParticleAttribute::Vorticity => "vorticity",
+            ParticleAttribute::Id => "id",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|attribute| attribute.name() == name)
+    }
+
+    // * One value per particle, None if the backend does not track what it is derived from
+    pub fn values(
+        self,
+        backend: &dyn SimulationBackend,
+        smoothing_length: f32,
+    ) -> Option<Vec<f32>> {
+        match self {
+            ParticleAttribute::Speed => Some(
+                backend
+                    .velocities()?
+                    .into_iter()
+                    .map(Vec3::length)
+                    .collect(),
+            ),
+            ParticleAttribute::Density => backend.densities(),
+            ParticleAttribute::Pressure => backend.pressures(),
+            ParticleAttribute::Vorticity => Some(vorticities(
+                &backend.positions(),
+                &backend.velocities()?,
+                2. * smoothing_length,
+            )),
+            ParticleAttribute::Id => Some((0..backend.num_particles()).map(|i| i as f32).collect()),
+        }
+    }
+}
+
+// * Vorticity magnitude from the relative motion of the neighbours within `support`, weighted
+// * towards the closer ones. Needs no masses or densities, so it works for every solver: for a
+// * rigid rotation with angular velocity w and evenly spread neighbours it gives |2w|.
+fn vorticities(positions: &[Vec3], velocities: &[Vec3], support: f32) -> Vec<f32> {
+    let cell = |p: Vec3| (p / support).floor().as_ivec3();
+    let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::new();
+    for (i, p) in positions.iter().enumerate() {
+        cells.entry(cell(*p)).or_default().push(i);
+    }
+
+    positions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let center = cell(*p);
+            let mut curl = Vec3::ZERO;
+            let mut weights = 0.;
+            for z in -1..=1 {
+                for y in -1..=1 {
+                    for x in -1..=1 {
+                        let Some(candidates) = cells.get(&(center + IVec3::new(x, y, z))) else {
+                            continue;
+                        };
+                        for &j in candidates {
+                            let offset = positions[j] - *p;
+                            let r = offset.length();
+                            if j == i || r >= support || r < 1.0e-6 {
+                                continue;
+                            }
+                            let weight = (1. - r / support).powi(2);
+                            curl += weight * offset.cross(velocities[j] - velocities[i]) / (r * r);
+                            weights += weight;
+                        }
+                    }
+                }
+            }
+            if weights > 0. {
+                (3. * curl / weights).length()
+            } else {
+                0.
+            }
+        })
+        .collect()
+}
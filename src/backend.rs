@@ -0,0 +1,132 @@
+use std::{io::SeekFrom, sync::Arc};
+
+use glam::{Vec3, Vec3A};
+use sph::{fluid::Fluid, sph::Particle};
+use wgpu::{BindGroupLayout, Buffer, Device, Queue};
+
+use crate::{
+    gpu_sph::GpuSph,
+    import::PointSequence,
+    pbf::Pbf,
+    recording::Replay,
+    scene::{FluidParams, Scene, SolverKind},
+};
+
+// * A particle solver, stepped on the simulation thread. Positions are in simulation units,
+// * `SimulationState` scales them into render space.
+pub trait SimulationBackend: Send {
+    fn step(&mut self, dt: f32);
+    fn positions(&self) -> Vec<Vec3>;
+    fn velocities(&self) -> Option<Vec<Vec3>> {
+        None
+    }
+    fn densities(&self) -> Option<Vec<f32>> {
+        None
+    }
+    fn pressures(&self) -> Option<Vec<f32>> {
+        None
+    }
+    fn num_particles(&self) -> usize;
+    // * Restarts from `particles`, which have to be as many as before
+    fn reset(&mut self, particles: Vec<Particle>);
+    // * True if `step` writes the simulation buffer itself, the positions are then not uploaded
+    fn writes_buffer(&self) -> bool {
+        false
+    }
+    // * Box the particles never leave. Lets a backend that writes the buffer itself skip
+    // * reading its positions back every frame just to fit the grids around them.
+    fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        None
+    }
+    // * Jumps to a frame, only for backends with a timeline like replays
+    fn seek(&mut self, _to: SeekFrom) {}
+    // * Time of the current frame on that timeline in seconds, it then replaces the stepped time
+    fn time(&self) -> Option<f64> {
+        None
+    }
+}
+
+// * `sph` crate on the CPU
+pub struct SphBackend {
+    fluid: Fluid,
+    params: FluidParams,
+    gravity: Vec3A,
+}
+
+impl SphBackend {
+    pub fn new(particles: Vec<Particle>, params: FluidParams, gravity: Vec3) -> Self {
+        let gravity = Vec3A::from(gravity);
+        Self {
+            fluid: Self::fluid(particles, &params, gravity),
+            params,
+            gravity,
+        }
+    }
+
+    fn fluid(particles: Vec<Particle>, params: &FluidParams, gravity: Vec3A) -> Fluid {
+        Fluid::new(
+            particles,
+            params.smoothing_length,
+            params.rest_density,
+            params.viscosity,
+            params.stiffness,
+            gravity,
+        )
+    }
+}
+
+impl SimulationBackend for SphBackend {
+    fn step(&mut self, dt: f32) {
+        self.fluid.step(dt);
+    }
+    fn positions(&self) -> Vec<Vec3> {
+        self.fluid.particles.iter().map(|p| p.pos.into()).collect()
+    }
+    fn velocities(&self) -> Option<Vec<Vec3>> {
+        Some(self.fluid.particles.iter().map(|p| p.vel.into()).collect())
+    }
+    fn densities(&self) -> Option<Vec<f32>> {
+        Some(self.fluid.particles.iter().map(|p| p.density).collect())
+    }
+    fn pressures(&self) -> Option<Vec<f32>> {
+        Some(self.fluid.particles.iter().map(|p| p.pressure).collect())
+    }
+    fn num_particles(&self) -> usize {
+        self.fluid.particles.len()
+    }
+    fn reset(&mut self, particles: Vec<Particle>) {
+        self.fluid = Self::fluid(particles, &self.params, self.gravity);
+    }
+}
+
+// * Everything a backend may need to set itself up
+pub struct BackendContext<'a> {
+    pub device: &'a Arc<Device>,
+    pub queue: &'a Arc<Queue>,
+    pub simulation_bind_group_layout: &'a BindGroupLayout,
+    pub buffer: &'a Arc<Buffer>,
+    // * Binding 1 of the simulation bind group, written by `SimulationState`
+    pub scalar_buffer: &'a Buffer,
+}
+
+// * The solver chosen by the scene, starting from its particles
+pub fn create_backend(
+    scene: &Scene,
+    context: &BackendContext,
+) -> Result<Box<dyn SimulationBackend>, String> {
+    let particles = scene.particles();
+    let gravity = Vec3::from(scene.gravity);
+    // * Replays and imports were opened once when the scene was loaded, the files may have
+    // * changed since
+    if let Some(path) = &scene.replay {
+        return Ok(Box::new(Replay::open(path)?));
+    }
+    if let Some(import) = &scene.import {
+        return Ok(Box::new(PointSequence::open(import)?));
+    }
+    Ok(match scene.solver {
+        SolverKind::Sph => Box::new(SphBackend::new(particles, scene.fluid, gravity)),
+        SolverKind::Gpu => Box::new(GpuSph::new(context, &scene.fluid, gravity, particles)),
+        SolverKind::Pbf => Box::new(Pbf::new(particles, scene.fluid, scene.pbf, gravity)),
+    })
+}
@@ -1,20 +1,39 @@
 use bytemuck::{Pod, Zeroable};
-use glam::{vec3, Mat3, Mat4, Vec3};
+use glam::{Mat3, Mat4, Vec3};
 use wgpu::{
     util::DeviceExt, BindGroup, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, Buffer,
     BufferUsages, Device, Queue, ShaderStages, SurfaceConfiguration,
 };
-use winit::event::{DeviceEvent, ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
 
 const SPEED: f32 = 100.;
 const SHIFT_SPEED: f32 = 0.1 * SPEED;
 const SENS: f32 = 0.1;
 
-const FOV_Y: f32 = 45.; // ! DEGREES
+// * Degrees of orbit per pixel dragged, and per second with the keys
+const ORBIT_SENS: f32 = 0.3;
+const ORBIT_KEY_SPEED: f32 = 90.;
+// * Fraction of the target distance per scroll line, and per second with the keys
+const ZOOM_STEP: f32 = 0.1;
+const ZOOM_KEY_SPEED: f32 = 1.;
+// * Fraction of the target distance panned per pixel dragged
+const PAN_SENS: f32 = 1.0e-3;
+// * Scroll pixels of a touchpad that count as one line
+const PIXELS_PER_LINE: f32 = 50.;
+const MIN_ORBIT_DISTANCE: f32 = 1.;
+
+pub const FOV_Y: f32 = 45.; // ! DEGREES
+const MIN_FOV_Y: f32 = 1.;
+const MAX_FOV_Y: f32 = 170.;
 
 // * +X = Right; +Y = Up; +Z = Back
 pub struct Camera {
     pub entity: CameraEntity,
+    // * Vertical field of view in degrees
+    pub fov_y: f32,
     pub view_matrix: Mat4,
     pub controller: CameraController,
     pub bind_group: BindGroup,
@@ -25,19 +44,18 @@ impl Camera {
     pub fn create_camera(
         device: &Device,
         config: &SurfaceConfiguration,
+        pos: Vec3,
+        dir: Vec3,
     ) -> (Self, BindGroupLayout) {
-        let pos = 0.7 * vec3(-111.74516, 193.78638, -38.64965);
-        let dir = vec3(0.5124362, -0.8005198, 0.31076893);
-        let screen_height = config.height as f32;
-        let screen_dist = (0.5 * screen_height) / (FOV_Y * 0.5).to_radians().tan();
-        let entity = CameraEntity {
+        let mut entity = CameraEntity {
             pos,
             dir: dir.normalize(),
             up: Vec3::Y,
-            screen_dist,
-            screen_width: config.width as f32,
-            screen_height,
+            screen_dist: 0.,
+            screen_width: 0.,
+            screen_height: 0.,
         };
+        entity.set_screen_size(config.width, config.height, FOV_Y);
         let view_matrix = Mat4::look_to_rh(entity.pos, entity.dir, entity.up);
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera Buffer"),
@@ -53,7 +71,7 @@ impl Camera {
             label: Some("Camera Bind Group Layout"),
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStages::FRAGMENT,
+                visibility: ShaderStages::VERTEX_FRAGMENT,
                 ty: wgpu::BindingType::Buffer {
                     ty: wgpu::BufferBindingType::Uniform,
                     has_dynamic_offset: false,
@@ -75,6 +93,7 @@ impl Camera {
         (
             Self {
                 entity,
+                fov_y: FOV_Y,
                 controller,
                 bind_group,
                 buffer,
@@ -87,6 +106,43 @@ impl Camera {
     pub fn update(&mut self, delta: f32, queue: &Queue) {
         self.controller
             .update_camera_entity(&mut self.entity, delta);
+        self.upload(queue);
+    }
+
+    pub fn set_pose(&mut self, pos: Vec3, dir: Vec3, queue: &Queue) {
+        self.entity.pos = pos;
+        self.entity.dir = dir.normalize();
+        self.upload(queue);
+    }
+
+    pub fn set_fov(&mut self, fov_y: f32, queue: &Queue) {
+        self.fov_y = fov_y.clamp(MIN_FOV_Y, MAX_FOV_Y);
+        let (width, height) = (self.entity.screen_width, self.entity.screen_height);
+        self.entity
+            .set_screen_size(width as u32, height as u32, self.fov_y);
+        self.upload(queue);
+    }
+
+    // * Orbiting turns the camera towards `target` from where it is
+    pub fn set_mode(&mut self, mode: CameraMode, target: Vec3, queue: &Queue) {
+        self.controller.mode = mode;
+        self.controller.target = target;
+        if mode == CameraMode::Orbit {
+            let dir = (target - self.entity.pos).normalize_or_zero();
+            if dir != Vec3::ZERO {
+                self.entity.dir = dir;
+            }
+        }
+        self.upload(queue);
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, queue: &Queue) {
+        self.entity.set_screen_size(width, height, self.fov_y);
+        self.upload(queue);
+    }
+
+    // * Recomputes the view matrix and writes the camera uniform
+    fn upload(&mut self, queue: &Queue) {
         self.view_matrix = Mat4::look_to_rh(self.entity.pos, self.entity.dir, self.entity.up);
 
         queue.write_buffer(
@@ -111,25 +167,71 @@ pub struct CameraEntity {
     pub screen_dist: f32,
 }
 
+impl CameraEntity {
+    // * Keeps the vertical field of view `fov_y`, in degrees
+    pub fn set_screen_size(&mut self, width: u32, height: u32, fov_y: f32) {
+        self.screen_width = width as f32;
+        self.screen_height = height as f32;
+        self.screen_dist = (0.5 * self.screen_height) / (fov_y * 0.5).to_radians().tan();
+    }
+}
+
+// * Fly: WASD plus mouse-look while the cursor is grabbed, Q / E down and up.
+// * Orbit: around `target`, left drag rotates, middle drag pans, scroll zooms; WASD / QE with
+// * the keys.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CameraMode {
+    Fly,
+    Orbit,
+}
+
+impl CameraMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            CameraMode::Fly => "fly",
+            CameraMode::Orbit => "orbit",
+        }
+    }
+}
+
 pub struct CameraController {
     speed: f32,
     sens: f32,
+    pub mode: CameraMode,
+    // * Render space point the orbit mode turns around
+    pub target: Vec3,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_rotating: bool,
+    is_panning: bool,
     delta: (f32, f32),
+    // * Mouse motion while a button was held since the last update
+    drag: (f32, f32),
+    // * Scroll lines since the last update
+    zoom: f32,
 }
 
 impl CameraController {
     pub fn new(speed: f32, sens: f32) -> Self {
         Self {
             speed,
+            mode: CameraMode::Fly,
+            target: Vec3::ZERO,
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_rotating: false,
+            is_panning: false,
             delta: (0., 0.),
+            drag: (0., 0.),
+            zoom: 0.,
             sens,
         }
     }
@@ -163,6 +265,14 @@ impl CameraController {
                         self.is_right_pressed = is_pressed;
                         true
                     }
+                    VirtualKeyCode::E => {
+                        self.is_up_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::Q => {
+                        self.is_down_pressed = is_pressed;
+                        true
+                    }
 
                     _ => false,
                 }
@@ -178,15 +288,50 @@ impl CameraController {
             _ => false,
         }
     }
+    // * Buttons and wheel of the orbit mode
+    pub fn handle_mouse_input(&mut self, event: &WindowEvent) -> bool {
+        if self.mode != CameraMode::Orbit {
+            return false;
+        }
+        match event {
+            WindowEvent::MouseInput { state, button, .. } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => self.is_rotating = is_pressed,
+                    MouseButton::Middle => self.is_panning = is_pressed,
+                    _ => return false,
+                }
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.zoom += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / PIXELS_PER_LINE,
+                };
+                true
+            }
+            _ => false,
+        }
+    }
     pub fn handle_mouse_movement(&mut self, event: &DeviceEvent) -> bool {
         if let DeviceEvent::MouseMotion { delta } = event {
             self.delta = (delta.0 as f32, delta.1 as f32);
+            if self.is_rotating || self.is_panning {
+                self.drag.0 += delta.0 as f32;
+                self.drag.1 += delta.1 as f32;
+            }
             true
         } else {
             false
         }
     }
     pub fn update_camera_entity(&mut self, camera_entity: &mut CameraEntity, dt: f32) {
+        match self.mode {
+            CameraMode::Fly => self.fly(camera_entity, dt),
+            CameraMode::Orbit => self.orbit(camera_entity, dt),
+        }
+    }
+    fn fly(&mut self, camera_entity: &mut CameraEntity, dt: f32) {
         camera_entity.dir = camera_entity.dir.normalize();
         let yaw = Mat3::from_rotation_y(-self.delta.0.to_radians() * self.sens);
         camera_entity.dir = yaw * camera_entity.dir;
@@ -224,6 +369,56 @@ impl CameraController {
         if self.is_left_pressed {
             camera_entity.pos -= right;
         }
+
+        let up = camera_entity.up * self.speed * dt;
+        if self.is_up_pressed {
+            camera_entity.pos += up;
+        }
+        if self.is_down_pressed {
+            camera_entity.pos -= up;
+        }
+    }
+    fn orbit(&mut self, camera_entity: &mut CameraEntity, dt: f32) {
+        let key_axis =
+            |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let offset = camera_entity.pos - self.target;
+        let mut distance = offset.length().max(MIN_ORBIT_DISTANCE);
+        let mut dir = (-offset / distance).normalize_or_zero();
+        if dir == Vec3::ZERO {
+            dir = camera_entity.dir;
+        }
+
+        // * ROTATE, THE SAME WAY ROUND AS THE FLY MOUSE-LOOK
+        let mut rotation = (0., 0.);
+        if self.is_rotating {
+            rotation = (self.drag.0 * ORBIT_SENS, self.drag.1 * ORBIT_SENS);
+        }
+        rotation.0 += key_axis(self.is_right_pressed, self.is_left_pressed) * ORBIT_KEY_SPEED * dt;
+        rotation.1 += key_axis(self.is_up_pressed, self.is_down_pressed) * ORBIT_KEY_SPEED * dt;
+        dir = Mat3::from_rotation_y(-rotation.0.to_radians()) * dir;
+        let right = dir.cross(camera_entity.up).normalize();
+        let pitched = Mat3::from_axis_angle(right, -rotation.1.to_radians()) * dir;
+        // * Stops short of the poles, where up and the view direction line up
+        if pitched.dot(camera_entity.up).abs() < 0.99 {
+            dir = pitched;
+        }
+
+        // * PAN THE TARGET IN THE VIEW PLANE, FOLLOWING THE CURSOR
+        if self.is_panning {
+            let view_up = right.cross(dir);
+            self.target += (view_up * self.drag.1 - right * self.drag.0) * distance * PAN_SENS;
+        }
+
+        // * ZOOM
+        let zoom_keys =
+            key_axis(self.is_forward_pressed, self.is_backward_pressed) * ZOOM_KEY_SPEED * dt;
+        distance *= (1. - ZOOM_STEP).powf(self.zoom) * (1. - zoom_keys.min(0.9));
+        distance = distance.max(MIN_ORBIT_DISTANCE);
+
+        self.drag = (0., 0.);
+        self.zoom = 0.;
+        camera_entity.dir = dir;
+        camera_entity.pos = self.target - dir * distance;
     }
 }
 #[repr(C)]
@@ -0,0 +1,234 @@
+use std::{
+    ops::{Add, Mul, Sub},
+    path::{Path, PathBuf},
+};
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::FOV_Y;
+
+// * How the camera moves from keyframe to keyframe
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    // * Passes through every keyframe
+    #[default]
+    CatmullRom,
+    // * Piecewise cubic Bezier: passes through keyframes 0, 3, 6, ..., the two keyframes between
+    // * each such pair only pull the curve towards them. Keyframes that do not complete a
+    // * segment are ignored.
+    Bezier,
+}
+
+// * Timing of the segment that starts at a keyframe
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2. - t),
+            Easing::EaseInOut => t * t * (3. - 2. * t),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    // * Simulation time in seconds
+    pub time: f64,
+    // * Render space
+    pub pos: [f32; 3],
+    pub dir: [f32; 3],
+    // * Vertical field of view in degrees
+    #[serde(default = "default_fov")]
+    pub fov: f32,
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+fn default_fov() -> f32 {
+    FOV_Y
+}
+
+// * Camera pose on a path
+pub struct Pose {
+    pub pos: Vec3,
+    pub dir: Vec3,
+    pub fov: f32,
+}
+
+// * Keyframed camera flight, stored as TOML. Played back against the simulation time, so a
+// * headless run renders the same flight every time.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraPath {
+    pub interpolation: Interpolation,
+    // * Ordered by time
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read camera path {}: {e}", path.display()))?;
+        let camera_path: Self = toml::from_str(&source)
+            .map_err(|e| format!("invalid camera path {}: {e}", path.display()))?;
+        camera_path
+            .validate()
+            .map_err(|e| format!("invalid camera path {}: {e}", path.display()))?;
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let source = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, source)
+            .map_err(|e| format!("failed to write camera path {}: {e}", path.display()))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for pair in self.keyframes.windows(2) {
+            if pair[1].time <= pair[0].time {
+                return Err(format!(
+                    "keyframe times must increase, got {} after {}",
+                    pair[1].time, pair[0].time
+                ));
+            }
+        }
+        for keyframe in &self.keyframes {
+            let dir = Vec3::from(keyframe.dir);
+            if !dir.is_finite() || dir == Vec3::ZERO {
+                return Err(format!(
+                    "keyframe dir must be a finite non-zero vector, got {dir}"
+                ));
+            }
+            if !(keyframe.fov > 0. && keyframe.fov < 180.) {
+                return Err(format!(
+                    "keyframe fov must be between 0 and 180 degrees, got {}",
+                    keyframe.fov
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // * Keeps the keyframes ordered, replacing one at the same time
+    pub fn add(&mut self, keyframe: Keyframe) {
+        match self
+            .keyframes
+            .binary_search_by(|k| k.time.total_cmp(&keyframe.time))
+        {
+            Ok(i) => self.keyframes[i] = keyframe,
+            Err(i) => self.keyframes.insert(i, keyframe),
+        }
+    }
+
+    // * Pose at a simulation time, held at the first and last keyframe outside the path
+    pub fn sample(&self, time: f64) -> Option<Pose> {
+        let keys = &self.keyframes;
+        if keys.is_empty() {
+            return None;
+        }
+        let (indices, u) = match self.interpolation {
+            Interpolation::CatmullRom => {
+                let (i, u) = Self::segment(keys, 1, time);
+                let last = keys.len() - 1;
+                (
+                    [i.saturating_sub(1), i, (i + 1).min(last), (i + 2).min(last)],
+                    u,
+                )
+            }
+            Interpolation::Bezier => {
+                let anchors = (keys.len() - 1) / 3 * 3 + 1;
+                let (i, u) = Self::segment(&keys[..anchors], 3, time);
+                if i + 3 >= anchors {
+                    ([i; 4], u)
+                } else {
+                    ([i, i + 1, i + 2, i + 3], u)
+                }
+            }
+        };
+        let pos = self.curve(indices.map(|i| Vec3::from(keys[i].pos)), u);
+        let dir = self.curve(indices.map(|i| Vec3::from(keys[i].dir).normalize()), u);
+        let fov = self.curve(indices.map(|i| keys[i].fov), u);
+        Some(Pose {
+            pos,
+            // * Opposite keyframe directions can cancel out
+            dir: dir
+                .try_normalize()
+                .unwrap_or(Vec3::from(keys[indices[1]].dir).normalize()),
+            fov,
+        })
+    }
+
+    fn curve<T>(&self, points: [T; 4], u: f32) -> T
+    where
+        T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+    {
+        match self.interpolation {
+            Interpolation::CatmullRom => catmull_rom(points, u),
+            Interpolation::Bezier => bezier(points, u),
+        }
+    }
+
+    // * First keyframe of the segment that contains `time`, stepping `stride` keyframes per
+    // * segment, and the eased position within it. `keys` must not be empty.
+    fn segment(keys: &[Keyframe], stride: usize, time: f64) -> (usize, f32) {
+        if keys.len() <= stride || time <= keys[0].time {
+            return (0, 0.);
+        }
+        let mut i = 0;
+        while i + stride < keys.len() - 1 && keys[i + stride].time <= time {
+            i += stride;
+        }
+        let (start, end) = (keys[i].time, keys[i + stride].time);
+        let u = ((time - start) / (end - start)).clamp(0., 1.) as f32;
+        (i, keys[i].easing.apply(u))
+    }
+}
+
+// * Uniform Catmull-Rom between `p[1]` and `p[2]`
+fn catmull_rom<T>(p: [T; 4], u: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let (u2, u3) = (u * u, u * u * u);
+    (p[1] * 2.
+        + (p[2] - p[0]) * u
+        + (p[0] * 2. - p[1] * 5. + p[2] * 4. - p[3]) * u2
+        + (p[1] * 3. - p[0] - p[2] * 3. + p[3]) * u3)
+        * 0.5
+}
+
+// * Cubic Bezier from `p[0]` to `p[3]`
+fn bezier<T>(p: [T; 4], u: f32) -> T
+where
+    T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+    let v = 1. - u;
+    p[0] * (v * v * v) + p[1] * (3. * v * v * u) + p[2] * (3. * v * u * u) + p[3] * (u * u * u)
+}
+
+// * Removes `--camera-path <file>` from the arguments
+pub fn take_camera_path_arg(args: &mut Vec<String>) -> Result<Option<PathBuf>, String> {
+    let Some(i) = args.iter().position(|arg| arg == "--camera-path") else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err("missing value for --camera-path".into());
+    }
+    let path = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(PathBuf::from(path)))
+}
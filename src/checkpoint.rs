@@ -0,0 +1,219 @@
+use std::{fs, path::Path};
+
+use glam::Vec3;
+use sph::sph::Particle;
+
+use crate::scene::FluidParams;
+
+const MAGIC: [u8; 8] = *b"FLUIDCKP";
+// * Bump whenever the layout of `Header` or `Record` changes
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+// * Little endian, followed by `num_particles` records
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    num_particles: u32,
+    time: f64,
+    smoothing_length: f32,
+    rest_density: f32,
+    viscosity: f32,
+    stiffness: f32,
+    gravity: [f32; 3],
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+struct Record {
+    pos: [f32; 3],
+    mass: f32,
+    vel: [f32; 3],
+    movable: u32,
+}
+
+// * Complete solver state at one moment, in simulation units
+#[derive(Clone)]
+pub struct Checkpoint {
+    // * Simulated seconds since the start of the scene
+    pub time: f64,
+    pub fluid: FluidParams,
+    pub gravity: [f32; 3],
+    pub positions: Vec<Vec3>,
+    pub velocities: Vec<Vec3>,
+    pub masses: Vec<f32>,
+    pub movable: Vec<bool>,
+}
+
+impl Checkpoint {
+    // * State at the start of a scene
+    pub fn new(particles: &[Particle], fluid: FluidParams, gravity: [f32; 3]) -> Self {
+        Self {
+            time: 0.,
+            fluid,
+            gravity,
+            positions: particles.iter().map(|p| p.pos.into()).collect(),
+            velocities: particles.iter().map(|p| p.vel.into()).collect(),
+            masses: particles.iter().map(|p| p.mass).collect(),
+            movable: particles.iter().map(|p| p.movable).collect(),
+        }
+    }
+
+    pub fn num_particles(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn particles(&self) -> Vec<Particle> {
+        (0..self.num_particles())
+            .map(|i| {
+                let mut particle =
+                    Particle::new(self.positions[i].into(), self.masses[i], self.movable[i]);
+                particle.vel = self.velocities[i].into();
+                particle
+            })
+            .collect()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_bytes())
+            .map_err(|e| format!("failed to write checkpoint {}: {e}", path.display()))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let header = Header {
+            magic: MAGIC,
+            version: CHECKPOINT_VERSION,
+            num_particles: self.num_particles() as u32,
+            time: self.time,
+            smoothing_length: self.fluid.smoothing_length,
+            rest_density: self.fluid.rest_density,
+            viscosity: self.fluid.viscosity,
+            stiffness: self.fluid.stiffness,
+            gravity: self.gravity,
+            _padding: 0,
+        };
+        let records: Vec<Record> = (0..self.num_particles())
+            .map(|i| Record {
+                pos: self.positions[i].to_array(),
+                mass: self.masses[i],
+                vel: self.velocities[i].to_array(),
+                movable: self.movable[i] as u32,
+            })
+            .collect();
+
+        let mut bytes = bytemuck::bytes_of(&header).to_vec();
+        bytes.extend_from_slice(bytemuck::cast_slice(&records));
+        bytes
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path)
+            .map_err(|e| format!("failed to read checkpoint {}: {e}", path.display()))?;
+        Self::parse(&bytes).map_err(|e| format!("invalid checkpoint {}: {e}", path.display()))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let header_size = std::mem::size_of::<Header>();
+        if bytes.len() < header_size {
+            return Err("file is too short".into());
+        }
+        let header: Header = bytemuck::pod_read_unaligned(&bytes[..header_size]);
+        if header.magic != MAGIC {
+            return Err("not a checkpoint file".into());
+        }
+        if header.version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "unsupported version {}, expected {CHECKPOINT_VERSION}",
+                header.version
+            ));
+        }
+        let data = &bytes[header_size..];
+        let record_size = std::mem::size_of::<Record>();
+        if data.len() != header.num_particles as usize * record_size {
+            return Err(format!(
+                "expected {} particles, found {} bytes of particle data",
+                header.num_particles,
+                data.len()
+            ));
+        }
+        let records: Vec<Record> = data
+            .chunks_exact(record_size)
+            .map(bytemuck::pod_read_unaligned)
+            .collect();
+
+        Ok(Self {
+            time: header.time,
+            fluid: FluidParams {
+                smoothing_length: header.smoothing_length,
+                rest_density: header.rest_density,
+                viscosity: header.viscosity,
+                stiffness: header.stiffness,
+            },
+            gravity: header.gravity,
+            positions: records.iter().map(|r| r.pos.into()).collect(),
+            velocities: records.iter().map(|r| r.vel.into()).collect(),
+            masses: records.iter().map(|r| r.mass).collect(),
+            movable: records.iter().map(|r| r.movable != 0).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint() -> Checkpoint {
+        let mut particles = vec![
+            Particle::new([1., 2., 3.].into(), 1.5, true),
+            Particle::new([-4., 5., 6.].into(), 2., false),
+        ];
+        particles[0].vel = [0.5, -1., 0.25].into();
+        let fluid = FluidParams {
+            smoothing_length: 1.,
+            rest_density: 2.,
+            viscosity: 3.,
+            stiffness: 4.,
+        };
+        let mut checkpoint = Checkpoint::new(&particles, fluid, [0., -9.81, 0.]);
+        checkpoint.time = 1.25;
+        checkpoint
+    }
+
+    #[test]
+    fn round_trip() {
+        let saved = checkpoint();
+        let loaded = Checkpoint::parse(&saved.to_bytes()).unwrap();
+        assert_eq!(loaded.time, saved.time);
+        assert!(loaded.fluid == saved.fluid);
+        assert_eq!(loaded.gravity, saved.gravity);
+        assert_eq!(loaded.positions, saved.positions);
+        assert_eq!(loaded.velocities, saved.velocities);
+        assert_eq!(loaded.masses, saved.masses);
+        assert_eq!(loaded.movable, saved.movable);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = checkpoint().to_bytes();
+        bytes[8..12].copy_from_slice(&(CHECKPOINT_VERSION + 1).to_le_bytes());
+        assert!(Checkpoint::parse(&bytes)
+            .err()
+            .unwrap()
+            .contains("unsupported version"));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let bytes = checkpoint().to_bytes();
+        assert!(Checkpoint::parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(
+            Checkpoint::parse(&bytes[..std::mem::size_of::<Header>() - 1])
+                .err()
+                .unwrap()
+                .contains("too short")
+        );
+        assert!(Checkpoint::parse(b"FLUIDREC").is_err());
+    }
+}
@@ -1,14 +1,215 @@
+use glam::Vec3;
+use serde::Deserialize;
+
+use crate::attribute::ParticleAttribute;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, Buffer,
-    BufferUsages, Device, ShaderStages,
+    BufferUsages, Device, Queue, ShaderStages,
 };
 
 pub const DEFAULT_DELTA_TIME: f32 = 0.01;
+// * CONTRIBUTIONS TO THE LOG-SUM-EXP BELOW THIS FRACTION ARE IGNORED BY THE GRID
+pub const KERNEL_EPSILON: f32 = 1.0e-3;
+// * alpha closer to 0 than this makes the log-sum-exp blend everything into one blob
+const MAX_ALPHA: f32 = -1.0e-3;
+// * Size of the light array in draw.wgsl
+pub const MAX_LIGHTS: usize = 4;
+
+// * What the ray marcher shows, for diagnosing surface artifacts and slow pixels
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ViewMode {
+    // * Lit surface over the background
+    Shaded = 0,
+    // * World space surface normal as colour
+    Normals = 1,
+    // * Heat map of the march steps per pixel, hits and misses alike
+    Steps = 2,
+    // * Heat map of the distance from the camera to the hit, from half to one and a half times
+    // * `slice_depth`
+    Depth = 3,
+    // * Field value on a plane `slice_depth` in front of the camera, with iso lines
+    Slice = 4,
+    // * Green for hits, red for rays that escaped, blue for rays that ran out of steps
+    MissReason = 5,
+    // * `attribute` of the particles blended at the hit, through `colormap`
+    Attribute = 6,
+}
+
+impl ViewMode {
+    pub const ALL: [ViewMode; 7] = [
+        ViewMode::Shaded,
+        ViewMode::Normals,
+        ViewMode::Steps,
+        ViewMode::Depth,
+        ViewMode::Slice,
+        ViewMode::MissReason,
+        ViewMode::Attribute,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ViewMode::Shaded => "shaded",
+            ViewMode::Normals => "normals",
+            ViewMode::Steps => "steps",
+            ViewMode::Depth => "depth",
+            ViewMode::Slice => "slice",
+            ViewMode::MissReason => "miss",
+            ViewMode::Attribute => "attribute",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|view| view.name() == name)
+    }
+}
+
+// * Maps the attribute range onto colours in `ViewMode::Attribute`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Colormap {
+    // * Perceptually uniform dark blue - green - yellow
+    #[default]
+    Viridis = 0,
+    // * Rainbow-like, for telling apart many levels
+    Turbo = 1,
+    // * Diverging blue - grey - red, for values around the middle of the range
+    Coolwarm = 2,
+}
+
+impl Colormap {
+    pub const ALL: [Colormap; 3] = [Colormap::Viridis, Colormap::Turbo, Colormap::Coolwarm];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Viridis => "viridis",
+            Colormap::Turbo => "turbo",
+            Colormap::Coolwarm => "coolwarm",
+        }
+    }
 
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|colormap| colormap.name() == name)
+    }
+}
+
+// * How particles that never move, e.g. the walls of a container, are drawn
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoundaryMode {
+    // * Part of the same surface as the fluid
+    #[default]
+    Blend = 0,
+    // * Only the fluid is drawn
+    Hide = 1,
+    // * Translucent shell in `boundary_color` over the fluid
+    Container = 2,
+    // * Opaque diffuse surface in `boundary_color`, kept apart from the fluid surface
+    Material = 3,
+}
+
+impl BoundaryMode {
+    pub const ALL: [BoundaryMode; 4] = [
+        BoundaryMode::Blend,
+        BoundaryMode::Hide,
+        BoundaryMode::Container,
+        BoundaryMode::Material,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BoundaryMode::Blend => "blend",
+            BoundaryMode::Hide => "hide",
+            BoundaryMode::Container => "container",
+            BoundaryMode::Material => "material",
+        }
+    }
+}
+
+// * Directional light for the specular highlights of the water
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Light {
+    // * World space direction towards the light
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+// * Water material of `ViewMode::Shaded`
+#[derive(Clone, PartialEq, Debug)]
+pub struct Shading {
+    // * Index of refraction, 1.33 for water
+    pub ior: f32,
+    // * Scales the radiance before tone mapping
+    pub exposure: f32,
+    // * Blinn-Phong exponent of the highlights, higher is sharper
+    pub shininess: f32,
+    // * Beer-Lambert coefficients per render unit of path inside the fluid, for red, green, blue
+    pub absorption: [f32; 3],
+    // * Light scattered inside the fluid, seen in place of what was absorbed
+    pub fluid_color: [f32; 3],
+    // * Tint of the container or albedo of the boundary material, see `BoundaryMode`
+    pub boundary_color: [f32; 3],
+    // * At most `MAX_LIGHTS`
+    pub lights: Vec<Light>,
+}
+
+impl Default for Shading {
+    fn default() -> Self {
+        Self {
+            ior: 1.33,
+            exposure: 1.,
+            shininess: 256.,
+            absorption: [0.045, 0.015, 0.008],
+            fluid_color: [0.02, 0.12, 0.2],
+            boundary_color: [0.7, 0.75, 0.8],
+            lights: vec![
+                Light {
+                    direction: [0.36, 0.8, 0.48],
+                    color: [1., 0.95, 0.85],
+                    intensity: 4.,
+                },
+                Light {
+                    direction: [-0.5, 0.3, -0.6],
+                    color: [0.6, 0.7, 1.],
+                    intensity: 0.5,
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Config {
     alpha: f32,
     radius: f32,
+    // * Ray marching iterations before giving up on a pixel
+    max_steps: u32,
+    // * Rays whose field value exceeds this leave the scene
+    max_distance: f32,
+    view: ViewMode,
+    // * View space distance of the plane shown by `ViewMode::Slice`
+    slice_depth: f32,
+    boundary: BoundaryMode,
+    // * Colouring of `ViewMode::Attribute`, the range None fits the fluid particles of each frame
+    attribute: ParticleAttribute,
+    colormap: Colormap,
+    color_range: Option<[f32; 2]>,
+    shading: Shading,
 }
 
 impl Default for Config {
@@ -16,21 +217,181 @@ impl Default for Config {
         Self {
             alpha: -0.2,
             radius: 5.,
+            max_steps: 100,
+            max_distance: 1000.,
+            view: ViewMode::Shaded,
+            slice_depth: 150.,
+            boundary: BoundaryMode::Blend,
+            attribute: ParticleAttribute::Pressure,
+            colormap: Colormap::Viridis,
+            color_range: None,
+            shading: Shading::default(),
         }
     }
 }
 
+impl Config {
+    pub fn new(alpha: f32, radius: f32, max_steps: u32, max_distance: f32) -> Self {
+        let mut config = Self::default();
+        config.set_alpha(alpha);
+        config.set_radius(radius);
+        config.set_max_steps(max_steps);
+        config.set_max_distance(max_distance);
+        config
+    }
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+    pub fn max_steps(&self) -> u32 {
+        self.max_steps
+    }
+    pub fn max_distance(&self) -> f32 {
+        self.max_distance
+    }
+    pub fn view(&self) -> ViewMode {
+        self.view
+    }
+    pub fn slice_depth(&self) -> f32 {
+        self.slice_depth
+    }
+    pub fn boundary(&self) -> BoundaryMode {
+        self.boundary
+    }
+    pub fn attribute(&self) -> ParticleAttribute {
+        self.attribute
+    }
+    // * The attribute the simulation has to provide, None while it is not shown
+    pub fn shown_attribute(&self) -> Option<ParticleAttribute> {
+        (self.view == ViewMode::Attribute).then_some(self.attribute)
+    }
+    pub fn colormap(&self) -> Colormap {
+        self.colormap
+    }
+    pub fn color_range(&self) -> Option<[f32; 2]> {
+        self.color_range
+    }
+    pub fn shading(&self) -> &Shading {
+        &self.shading
+    }
+    // * The setters clamp to values the shaders can work with
+    pub fn set_alpha(&mut self, alpha: f32) {
+        self.alpha = alpha.min(MAX_ALPHA);
+    }
+    pub fn set_radius(&mut self, radius: f32) {
+        self.radius = radius.max(0.);
+    }
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.max_steps = max_steps.max(1);
+    }
+    pub fn set_max_distance(&mut self, max_distance: f32) {
+        self.max_distance = max_distance.max(self.radius);
+    }
+    pub fn set_view(&mut self, view: ViewMode) {
+        self.view = view;
+    }
+    pub fn set_slice_depth(&mut self, slice_depth: f32) {
+        self.slice_depth = slice_depth.max(0.);
+    }
+    pub fn set_boundary(&mut self, boundary: BoundaryMode) {
+        self.boundary = boundary;
+    }
+    pub fn set_attribute(&mut self, attribute: ParticleAttribute) {
+        self.attribute = attribute;
+    }
+    pub fn set_colormap(&mut self, colormap: Colormap) {
+        self.colormap = colormap;
+    }
+    // * An empty or reversed range is widened so that it maps somewhere
+    pub fn set_color_range(&mut self, color_range: Option<[f32; 2]>) {
+        self.color_range = color_range.map(|[min, max]| [min, max.max(min + f32::EPSILON)]);
+    }
+    // * Lights beyond `MAX_LIGHTS` are dropped
+    pub fn set_shading(&mut self, mut shading: Shading) {
+        shading.ior = shading.ior.max(1.);
+        shading.exposure = shading.exposure.max(0.);
+        shading.shininess = shading.shininess.max(1.);
+        shading.absorption = shading.absorption.map(|a| a.max(0.));
+        shading.fluid_color = shading.fluid_color.map(|c| c.max(0.));
+        shading.boundary_color = shading.boundary_color.map(|c| c.clamp(0., 1.));
+        shading.lights.truncate(MAX_LIGHTS);
+        self.shading = shading;
+    }
+    // * Whether the mesh and the screen space fluid, which have no surface of their own for
+    // * the boundary, build the fluid surface over the boundary particles too
+    pub fn surface_includes_boundary(&self) -> bool {
+        self.boundary == BoundaryMode::Material
+    }
+    // * Distance beyond which a particle no longer noticeably contributes to the surface
+    pub fn influence_range(&self) -> f32 {
+        self.radius + KERNEL_EPSILON.ln() / self.alpha
+    }
+}
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, Default)]
+struct ShaderLight {
+    // * Normalised
+    direction: [f32; 3],
+    intensity: f32,
+    color: [f32; 3],
+    _padding: f32,
+}
+
 #[repr(C)]
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
 pub struct DrawShaderConfig {
     alpha: f32,
     min_dist: f32,
+    max_steps: u32,
+    max_distance: f32,
+    view: u32,
+    slice_depth: f32,
+    ior: f32,
+    exposure: f32,
+    absorption: [f32; 3],
+    shininess: f32,
+    fluid_color: [f32; 3],
+    num_lights: u32,
+    boundary_color: [f32; 3],
+    boundary: u32,
+    color_range: [f32; 2],
+    colormap: u32,
+    fit_range: u32,
+    lights: [ShaderLight; MAX_LIGHTS],
 }
 impl From<&Config> for DrawShaderConfig {
     fn from(cfg: &Config) -> Self {
+        let mut lights = [ShaderLight::default(); MAX_LIGHTS];
+        for (light, shader_light) in cfg.shading.lights.iter().zip(&mut lights) {
+            *shader_light = ShaderLight {
+                direction: Vec3::from(light.direction).normalize_or_zero().to_array(),
+                intensity: light.intensity,
+                color: light.color,
+                _padding: 0.,
+            };
+        }
         Self {
             alpha: cfg.alpha,
             min_dist: cfg.radius,
+            max_steps: cfg.max_steps,
+            max_distance: cfg.max_distance,
+            view: cfg.view as u32,
+            slice_depth: cfg.slice_depth,
+            ior: cfg.shading.ior,
+            exposure: cfg.shading.exposure,
+            absorption: cfg.shading.absorption,
+            shininess: cfg.shading.shininess,
+            fluid_color: cfg.shading.fluid_color,
+            num_lights: cfg.shading.lights.len() as u32,
+            boundary_color: cfg.shading.boundary_color,
+            boundary: cfg.boundary as u32,
+            color_range: cfg.color_range.unwrap_or_default(),
+            colormap: cfg.colormap as u32,
+            fit_range: cfg.color_range.is_none() as u32,
+            lights,
         }
     }
 }
@@ -39,10 +400,14 @@ impl DrawShaderConfig {
         device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Config Buffer"),
             contents: bytemuck::bytes_of(self),
-            usage: BufferUsages::UNIFORM,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         })
     }
-    pub fn create_bind_group(&self, device: &Device) -> (BindGroupLayout, BindGroup) {
+    // * Updates a buffer created by `as_buffer`
+    pub fn write(&self, queue: &Queue, buffer: &Buffer) {
+        queue.write_buffer(buffer, 0, bytemuck::bytes_of(self));
+    }
+    pub fn create_bind_group(&self, device: &Device) -> (BindGroupLayout, BindGroup, Buffer) {
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Render Bind Group Layout"),
             entries: &[BindGroupLayoutEntry {
@@ -56,14 +421,15 @@ impl DrawShaderConfig {
                 count: None,
             }],
         });
+        let buffer = self.as_buffer(device);
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: Some("Render Bind Group"),
             layout: &bind_group_layout,
             entries: &[BindGroupEntry {
                 binding: 0,
-                resource: self.as_buffer(device).as_entire_binding(),
+                resource: buffer.as_entire_binding(),
             }],
         });
-        (bind_group_layout, bind_group)
+        (bind_group_layout, bind_group, buffer)
     }
 }
@@ -1,5 +1,58 @@
-const MAX_DISTANCE = 1000.;
-const MAX_STEPS: f32 = 100.;
+// * log_sum_exp_grid without any particle in range is +inf
+const EMPTY_FIELD = 1.0e30;
+
+// * Must match `ViewMode` in config.rs
+const VIEW_SHADED = 0u;
+const VIEW_NORMALS = 1u;
+const VIEW_STEPS = 2u;
+const VIEW_DEPTH = 3u;
+const VIEW_SLICE = 4u;
+const VIEW_MISS_REASON = 5u;
+const VIEW_ATTRIBUTE = 6u;
+
+// * Must match `Colormap` in config.rs
+const COLORMAP_VIRIDIS = 0u;
+const COLORMAP_TURBO = 1u;
+const COLORMAP_COOLWARM = 2u;
+
+// * Must match `BoundaryMode` in config.rs
+const BOUNDARY_BLEND = 0u;
+const BOUNDARY_HIDE = 1u;
+const BOUNDARY_CONTAINER = 2u;
+const BOUNDARY_MATERIAL = 3u;
+
+// * Same as PARTICLE_BOUNDARY in simulation.rs, stored in the w of every position
+const PARTICLE_BOUNDARY = 1.;
+// * Which particles a field is taken over
+const FLUID_PARTICLES = 1u;
+const BOUNDARY_PARTICLES = 2u;
+const ALL_PARTICLES = 3u;
+// * Fluid and boundary as two surfaces that do not blend into each other
+const SEPARATE_PARTICLES = 4u;
+
+// * Opacity of a container wall seen head on and at a grazing angle
+const CONTAINER_OPACITY = 0.15;
+const CONTAINER_RIM_OPACITY = 0.8;
+
+// * How a ray ended
+const HIT = 0u;
+const MISS_ESCAPED = 1u;
+const MISS_STEPS = 2u;
+
+// * Same as BACKGROUND_COLOR in render.rs
+const BACKGROUND = vec3<f32>(0.1, 0.2, 0.3);
+
+// * Same as MAX_LIGHTS in config.rs
+const MAX_LIGHTS = 4;
+// * Procedural environment, +Y is up like the camera
+const SKY_ZENITH = vec3<f32>(0.15, 0.35, 0.8);
+const SKY_HORIZON = vec3<f32>(0.75, 0.85, 0.95);
+const GROUND_HORIZON = vec3<f32>(0.45, 0.42, 0.38);
+const GROUND = vec3<f32>(0.08, 0.07, 0.06);
+// * Interior march steps in units of config.radius: the field inside is no distance bound, so
+// * steps are capped, and kept from stalling close to the surface
+const MIN_INTERIOR_STEP = 0.1;
+const MAX_INTERIOR_STEP = 1.;
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
 };
@@ -20,9 +73,48 @@ struct Camera {
     inverse_view_matrix: mat4x4<f32>
 }
 
+struct Light {
+    // * World space, towards the light
+    direction: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+}
+
 struct Config {
     alpha: f32,
     radius: f32,
+    max_steps: u32,
+    max_distance: f32,
+    view: u32,
+    slice_depth: f32,
+    ior: f32,
+    exposure: f32,
+    absorption: vec3<f32>,
+    shininess: f32,
+    fluid_color: vec3<f32>,
+    num_lights: u32,
+    boundary_color: vec3<f32>,
+    boundary: u32,
+    color_range: vec2<f32>,
+    colormap: u32,
+    fit_range: u32,
+    lights: array<Light, MAX_LIGHTS>,
+}
+
+// * Written by `SimulationState` for the attribute view
+struct Scalars {
+    // * Over the fluid particles of the frame
+    range: vec2<f32>,
+    values: array<f32>,
+}
+
+struct Grid {
+    origin: vec3<f32>,
+    cell_size: f32,
+    dims: vec3<u32>,
+    cutoff: f32,
+    num_particles: u32,
+    enabled: u32,
 }
 
 @group(0) @binding(0)
@@ -32,7 +124,18 @@ var<uniform> camera: Camera;
 var<uniform> config: Config;
 
 @group(2) @binding(0)
-var<storage> positions: array<vec3<f32>>;
+var<storage> positions: array<vec4<f32>>;
+@group(2) @binding(1)
+var<storage> scalars: Scalars;
+
+@group(3) @binding(0)
+var<uniform> grid: Grid;
+@group(3) @binding(1)
+var<storage> cell_counts: array<u32>;
+@group(3) @binding(2)
+var<storage> cell_starts: array<u32>;
+@group(3) @binding(3)
+var<storage> sorted_indices: array<u32>;
 
 @vertex
 fn vs_main(
@@ -45,25 +148,298 @@ fn vs_main(
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let ray_dir = normalize(vec3<f32>(in.clip_position.x - camera.screen_width * 0.5, -in.clip_position.y + camera.screen_height * 0.5, -camera.screen_dist));
 
+    if config.view == VIEW_SLICE {
+        return vec4<f32>(slice_color(ray_dir), 1.);
+    }
+
+    let particles = surface_particles();
+    let ray = march(ray_dir, particles);
+
+    if config.view == VIEW_STEPS {
+        return vec4<f32>(heat(f32(ray.steps) / f32(config.max_steps)), 1.);
+    }
+    if config.view == VIEW_MISS_REASON {
+        return vec4<f32>(f32(ray.result == MISS_ESCAPED), f32(ray.result == HIT), f32(ray.result == MISS_STEPS), 1.);
+    }
+    let view_dir = normalize((camera.inverse_view_matrix * vec4<f32>(ray_dir, 0.)).xyz);
+    var color = environment(view_dir);
+    if ray.result == HIT {
+        let surface = hit_particles(ray.pos, particles);
+        let normal = field_normal(ray.pos, surface);
+        if config.view == VIEW_NORMALS {
+            return vec4<f32>(normal * 0.5 + 0.5, 1.);
+        }
+        if config.view == VIEW_DEPTH {
+            // * Centred on the slice plane, so it can be moved to where the detail is
+            return vec4<f32>(heat(length(ray.pos) / config.slice_depth - 0.5), 1.);
+        }
+        if config.view == VIEW_ATTRIBUTE {
+            return vec4<f32>(attribute_color(ray.pos, surface, normal, view_dir), 1.);
+        }
+        if surface == BOUNDARY_PARTICLES {
+            color = shade_boundary(normal);
+        } else {
+            color = shade_water(ray.pos, normal, view_dir);
+        }
+    } else if config.view != VIEW_SHADED {
+        return vec4<f32>(BACKGROUND, 1.);
+    }
+    if config.boundary == BOUNDARY_CONTAINER {
+        color = over_container(ray_dir, view_dir, ray, color);
+    }
+    return vec4<f32>(tone_map(color), 1.);
+}
+
+struct March {
+    // * View space, where the ray stopped
+    pos: vec3<f32>,
+    result: u32,
+    steps: u32,
+}
+
+// * Sphere traces the surface of `particles` from the camera
+fn march(ray_dir: vec3<f32>, particles: u32) -> March {
     var ray_pos = vec3<f32>(0., 0., 0.);
-    for (var i = 0u; i < 100u; i++) {
-        let min_dist = log_sum_exp(ray_pos, camera.view_matrix, config.alpha);
-        if min_dist >= MAX_DISTANCE {
-            return vec4<f32>(1., 0., 0., 1.);
+    for (var i = 0u; i < config.max_steps; i++) {
+        let min_dist = field(ray_pos, particles);
+        if min_dist >= config.max_distance {
+            return March(ray_pos, MISS_ESCAPED, i);
         }
         if min_dist < config.radius * 1.01 {
-            return camera.inverse_view_matrix * (vec4<f32>(normalize(log_sum_exp_grad(ray_pos, camera.view_matrix, config.alpha)), 0.0));
-            // return vec4<f32>(0., 1., 0., 1.);
+            return March(ray_pos, HIT, i);
         }
         ray_pos += ray_dir * (min_dist - config.radius);
     }
-    return vec4<f32>(0., 0., 1., 1.);
+    return March(ray_pos, MISS_STEPS, config.max_steps);
+}
+
+// * Particles whose surface the ray marcher looks for
+fn surface_particles() -> u32 {
+    if config.boundary == BOUNDARY_HIDE || config.boundary == BOUNDARY_CONTAINER {
+        return FLUID_PARTICLES;
+    }
+    if config.boundary == BOUNDARY_MATERIAL {
+        return SEPARATE_PARTICLES;
+    }
+    return ALL_PARTICLES;
+}
+
+// * Which of the separate surfaces a hit at a view space position belongs to
+fn hit_particles(ray_pos: vec3<f32>, particles: u32) -> u32 {
+    if particles != SEPARATE_PARTICLES {
+        return particles;
+    }
+    if field(ray_pos, BOUNDARY_PARTICLES) < field(ray_pos, FLUID_PARTICLES) {
+        return BOUNDARY_PARTICLES;
+    }
+    return FLUID_PARTICLES;
+}
+
+// * Radiance of the water surface hit at the view space `hit_pos` along the world space `view_dir`:
+// * Schlick Fresnel blend of the reflected and refracted environment plus Blinn-Phong highlights
+// * of the lights. The refracted light is absorbed along its path through the fluid.
+fn shade_water(hit_pos: vec3<f32>, normal: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    let cos_theta = clamp(dot(normal, -view_dir), 0., 1.);
+    let f0 = pow((config.ior - 1.) / (config.ior + 1.), 2.);
+    let fresnel = f0 + (1. - f0) * pow(1. - cos_theta, 5.);
+
+    let reflected = environment(reflect(view_dir, normal));
+    let refract_dir = refract(view_dir, normal, 1. / config.ior);
+    // * refract returns zero on total internal reflection, which can not happen entering water
+    // * Light only travels through the walls too if they are part of the fluid surface
+    let particles = select(FLUID_PARTICLES, ALL_PARTICLES, config.boundary == BOUNDARY_BLEND);
+    let path = thickness(hit_pos, (camera.view_matrix * vec4<f32>(refract_dir, 0.)).xyz, particles);
+    let transmittance = exp(-config.absorption * path);
+    let refracted = environment(refract_dir) * transmittance + config.fluid_color * (1. - transmittance);
+    var color = mix(refracted, reflected, fresnel);
+
+    // * Normalised so that sharper highlights keep their energy
+    let normalization = (config.shininess + 8.) / (8. * 3.14159265);
+    for (var i = 0u; i < min(config.num_lights, u32(MAX_LIGHTS)); i++) {
+        let light = config.lights[i];
+        let half_dir = normalize(light.direction - view_dir);
+        let specular = normalization * pow(max(dot(normal, half_dir), 0.), config.shininess);
+        color += light.color * light.intensity * specular * fresnel * max(dot(normal, light.direction), 0.);
+    }
+    return color;
+}
+
+// * Path length inside the fluid from the view space `entry` along `dir` until the field of
+// * `particles` rises above the surface again
+fn thickness(entry: vec3<f32>, dir: vec3<f32>, particles: u32) -> f32 {
+    var travelled = config.radius * MIN_INTERIOR_STEP;
+    for (var i = 0u; i < config.max_steps; i++) {
+        let depth = config.radius - field(entry + dir * travelled, particles);
+        if depth <= 0. || travelled >= config.max_distance {
+            break;
+        }
+        travelled += clamp(depth, config.radius * MIN_INTERIOR_STEP, config.radius * MAX_INTERIOR_STEP);
+    }
+    return travelled;
+}
+
+// * Radiance of an opaque diffuse boundary surface with the world space `normal`, lit by the
+// * environment and the lights
+fn shade_boundary(normal: vec3<f32>) -> vec3<f32> {
+    var irradiance = vec3<f32>(0.);
+    for (var i = 0u; i < min(config.num_lights, u32(MAX_LIGHTS)); i++) {
+        let light = config.lights[i];
+        irradiance += light.color * light.intensity * max(dot(normal, light.direction), 0.);
+    }
+    return config.boundary_color * (environment(normal) + irradiance / 3.14159265);
+}
+
+// * `color` seen through the nearest container wall along the ray, if the wall is in front of
+// * the fluid hit by `fluid`. Walls are more opaque at grazing angles, like glass.
+fn over_container(ray_dir: vec3<f32>, view_dir: vec3<f32>, fluid: March, color: vec3<f32>) -> vec3<f32> {
+    let wall = march(ray_dir, BOUNDARY_PARTICLES);
+    if wall.result != HIT || (fluid.result == HIT && length(wall.pos) >= length(fluid.pos)) {
+        return color;
+    }
+    let normal = field_normal(wall.pos, BOUNDARY_PARTICLES);
+    let opacity = mix(CONTAINER_RIM_OPACITY, CONTAINER_OPACITY, abs(dot(normal, view_dir)));
+    return mix(color, shade_boundary(normal), opacity);
+}
+
+// * Sky gradient above the horizon, a ground darkening towards the nadir, a sun disc per light
+fn environment(dir: vec3<f32>) -> vec3<f32> {
+    var color = mix(GROUND_HORIZON, GROUND, pow(max(-dir.y, 0.), 0.5));
+    if dir.y > 0. {
+        color = mix(SKY_HORIZON, SKY_ZENITH, pow(dir.y, 0.5));
+    }
+    for (var i = 0u; i < min(config.num_lights, u32(MAX_LIGHTS)); i++) {
+        let light = config.lights[i];
+        color += light.color * light.intensity * smoothstep(0.9995, 0.9999, dot(dir, light.direction));
+    }
+    return color;
+}
+
+// * Exposure followed by an exponential tone curve into 0-1
+fn tone_map(radiance: vec3<f32>) -> vec3<f32> {
+    return 1. - exp(-radiance * config.exposure);
+}
+
+// * Field on the plane `config.slice_depth` in front of the camera: warm inside the surface,
+// * cool outside, with an iso line every `config.radius` and a white one on the surface
+fn slice_color(ray_dir: vec3<f32>) -> vec3<f32> {
+    let value = field(ray_dir * (config.slice_depth / -ray_dir.z), surface_particles());
+    let bands = abs(fract(value / config.radius + 0.5) - 0.5);
+    let iso_line = 1. - smoothstep(0., 0.05, bands);
+    if abs(value - config.radius) < config.radius * 0.05 {
+        return vec3<f32>(1.);
+    }
+    var color = vec3<f32>(0.2, 0.4, 1.) * exp(-max(value - config.radius, 0.) / config.max_distance * 4.);
+    if value < config.radius {
+        color = vec3<f32>(1., 0.5, 0.1);
+    }
+    return mix(color, vec3<f32>(0.), iso_line * 0.6);
+}
+
+// * Attribute of `particles` blended at the view space `hit_pos` through the colormap, lit from
+// * the camera so that the colours stay comparable
+fn attribute_color(hit_pos: vec3<f32>, particles: u32, normal: vec3<f32>, view_dir: vec3<f32>) -> vec3<f32> {
+    var range = config.color_range;
+    if config.fit_range != 0u {
+        range = scalars.range;
+    }
+    let t = (field_scalar(hit_pos, particles) - range.x) / max(range.y - range.x, 1.0e-30);
+    // * The colormaps are defined in sRGB, the target converts back
+    let color = pow(colormap(clamp(t, 0., 1.)), vec3<f32>(2.2));
+    return color * (0.35 + 0.65 * abs(dot(normal, view_dir)));
+}
+
+// * `config.colormap` at t from 0 to 1, in sRGB
+fn colormap(t: f32) -> vec3<f32> {
+    if config.colormap == COLORMAP_TURBO {
+        // * Polynomial fit of Turbo by Anton Mikhailov
+        let v4 = vec4<f32>(1., t, t * t, t * t * t);
+        let v2 = v4.zw * v4.z;
+        return clamp(vec3<f32>(
+            dot(v4, vec4<f32>(0.13572138, 4.61539260, -42.66032258, 132.13108234)) + dot(v2, vec2<f32>(-152.94239396, 59.28637943)),
+            dot(v4, vec4<f32>(0.09140261, 2.19418839, 4.84296658, -14.18503333)) + dot(v2, vec2<f32>(4.27729857, 2.82956604)),
+            dot(v4, vec4<f32>(0.10667330, 12.64194608, -60.58204836, 110.36276771)) + dot(v2, vec2<f32>(-89.90310912, 27.34824973)),
+        ), vec3<f32>(0.), vec3<f32>(1.));
+    }
+    if config.colormap == COLORMAP_COOLWARM {
+        // * Moreland's diverging map through five of its samples
+        let x = t * 4.;
+        let i = min(u32(x), 3u);
+        var stops = array<vec3<f32>, 5>(
+            vec3<f32>(0.230, 0.299, 0.754),
+            vec3<f32>(0.552, 0.690, 0.996),
+            vec3<f32>(0.865, 0.865, 0.865),
+            vec3<f32>(0.958, 0.603, 0.482),
+            vec3<f32>(0.706, 0.016, 0.150),
+        );
+        return mix(stops[i], stops[i + 1u], x - f32(i));
+    }
+    // * Polynomial fit of viridis by Matt Zucker
+    let c0 = vec3<f32>(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+    let c1 = vec3<f32>(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+    let c2 = vec3<f32>(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+    let c3 = vec3<f32>(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+    let c4 = vec3<f32>(6.228269936347081, 14.17993336680509, 56.69055260068105);
+    let c5 = vec3<f32>(4.776384997670288, -13.74514537774601, -65.35303263337234);
+    let c6 = vec3<f32>(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+    return clamp(c0 + t * (c1 + t * (c2 + t * (c3 + t * (c4 + t * (c5 + t * c6))))), vec3<f32>(0.), vec3<f32>(1.));
+}
+
+// * Blue - cyan - green - yellow - red for t from 0 to 1
+fn heat(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0., 1.) * 4.;
+    return clamp(vec3<f32>(x - 2., min(x, 4. - x), 2. - x), vec3<f32>(0.), vec3<f32>(1.));
+}
+
+// * Field of `particles` at a view space position
+fn field(ray_pos: vec3<f32>, particles: u32) -> f32 {
+    if particles == SEPARATE_PARTICLES {
+        return min(subset_field(ray_pos, FLUID_PARTICLES), subset_field(ray_pos, BOUNDARY_PARTICLES));
+    }
+    return subset_field(ray_pos, particles);
+}
+
+// * Same as field, but only for the *_PARTICLES bits, using the grid if it is enabled
+fn subset_field(ray_pos: vec3<f32>, particles: u32) -> f32 {
+    if grid.enabled == 0u {
+        return log_sum_exp(ray_pos, camera.view_matrix, config.alpha, particles);
+    }
+    let world_pos = (camera.inverse_view_matrix * vec4<f32>(ray_pos, 1.)).xyz;
+    let value = log_sum_exp_grid(world_pos, config.alpha, particles);
+    if value >= EMPTY_FIELD {
+        // * No particle within the cutoff, so every particle is at least this far away
+        return grid_box_distance(world_pos) + grid.cutoff;
+    }
+    return value;
+}
+
+// * World space surface normal of `particles` at a view space position
+fn field_normal(ray_pos: vec3<f32>, particles: u32) -> vec3<f32> {
+    if grid.enabled == 0u {
+        return (camera.inverse_view_matrix * vec4<f32>(normalize(log_sum_exp_grad(ray_pos, camera.view_matrix, config.alpha, particles)), 0.)).xyz;
+    }
+    let world_pos = (camera.inverse_view_matrix * vec4<f32>(ray_pos, 1.)).xyz;
+    return normalize(log_sum_exp_grad_grid(world_pos, config.alpha, particles));
+}
+
+// * Attribute of `particles` at a view space position, using the grid if it is enabled
+fn field_scalar(ray_pos: vec3<f32>, particles: u32) -> f32 {
+    if grid.enabled == 0u {
+        return log_sum_exp_scalar(ray_pos, camera.view_matrix, config.alpha, particles);
+    }
+    let world_pos = (camera.inverse_view_matrix * vec4<f32>(ray_pos, 1.)).xyz;
+    return log_sum_exp_scalar_grid(world_pos, config.alpha, particles);
+}
+
+// * Whether particle `i` is one of `particles`
+fn selected(i: u32, particles: u32) -> bool {
+    let boundary = positions[i].w > 0.5 * PARTICLE_BOUNDARY;
+    return (particles & select(FLUID_PARTICLES, BOUNDARY_PARTICLES, boundary)) != 0u;
 }
 
 fn min_distance(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>) -> f32 {
     var minimum = 1. / 0.;
     for (var i = 0u; i < arrayLength(&positions); i++) {
-        let pos = (camera.view_matrix * vec4<f32>(positions[i], 1.)).xyz;
+        let pos = (camera.view_matrix * vec4<f32>(positions[i].xyz, 1.)).xyz;
         let dist = distance(pos, ray_pos) ;
         minimum = min(minimum, dist);
     }
@@ -71,27 +447,36 @@ fn min_distance(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>) -> f32 {
 }
 
 
-fn log_sum_exp(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>, alpha: f32) -> f32 {
+fn log_sum_exp(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>, alpha: f32, particles: u32) -> f32 {
     var res = 0.;
 
     for (var i = 0u; i < arrayLength(&positions); i++) {
-        let pos = (camera.view_matrix * vec4<f32>(positions[i], 1.)).xyz;
+        if !selected(i, particles) {
+            continue;
+        }
+        let pos = (camera.view_matrix * vec4<f32>(positions[i].xyz, 1.)).xyz;
         let dist = distance(pos, ray_pos);
         res += exp(alpha * dist);
     }
     return log(res) / alpha;
 }
-fn log_sum_exp_grad(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>, alpha: f32) -> vec3<f32> {
+fn log_sum_exp_grad(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>, alpha: f32, particles: u32) -> vec3<f32> {
 
     var sum_exp = 0.;
     for (var i = 0u; i < arrayLength(&positions); i++) {
-        let pos = (camera.view_matrix * vec4<f32>(positions[i], 1.)).xyz;
+        if !selected(i, particles) {
+            continue;
+        }
+        let pos = (camera.view_matrix * vec4<f32>(positions[i].xyz, 1.)).xyz;
         let dist = distance(pos, ray_pos);
         sum_exp += exp(alpha * dist);
     }
     var gradient = vec3<f32>(0., 0., 0.);
     for (var i = 0u; i < arrayLength(&positions); i++) {
-        let pos = (camera.view_matrix * vec4<f32>(positions[i], 1.)).xyz;
+        if !selected(i, particles) {
+            continue;
+        }
+        let pos = (camera.view_matrix * vec4<f32>(positions[i].xyz, 1.)).xyz;
         let dist = distance(pos, ray_pos);
         let partial_derivative = exp(dist * alpha) / sum_exp;
         let dist_gradient = -normalize(pos - ray_pos);
@@ -100,6 +485,123 @@ fn log_sum_exp_grad(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>, alpha: f32) ->
     return gradient;
 }
 
+// * Particle scalars averaged with the softmax weights of log_sum_exp_grad
+fn log_sum_exp_scalar(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>, alpha: f32, particles: u32) -> f32 {
+    var sum_exp = 0.;
+    var weighted_sum = 0.;
+    for (var i = 0u; i < arrayLength(&positions); i++) {
+        if !selected(i, particles) {
+            continue;
+        }
+        let pos = (camera.view_matrix * vec4<f32>(positions[i].xyz, 1.)).xyz;
+        let weight = exp(alpha * distance(pos, ray_pos));
+        sum_exp += weight;
+        weighted_sum += weight * scalars.values[i];
+    }
+    return weighted_sum / sum_exp;
+}
+
+fn grid_cell(pos: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor((pos - grid.origin) / grid.cell_size));
+}
+
+fn grid_cell_index(cell: vec3<i32>) -> u32 {
+    return u32(cell.x) + grid.dims.x * (u32(cell.y) + grid.dims.y * u32(cell.z));
+}
+
+// * Distance from a world space position to the box covered by the grid, 0 inside
+fn grid_box_distance(pos: vec3<f32>) -> f32 {
+    let half_size = vec3<f32>(grid.dims) * grid.cell_size * 0.5;
+    let center = grid.origin + half_size;
+    return length(max(abs(pos - center) - half_size, vec3<f32>(0.)));
+}
+
+// * Same as log_sum_exp, but only over particles within grid.cutoff of a world space position
+fn log_sum_exp_grid(pos: vec3<f32>, alpha: f32, particles: u32) -> f32 {
+    let lo = max(grid_cell(pos - grid.cutoff), vec3<i32>(0));
+    let hi = min(grid_cell(pos + grid.cutoff), vec3<i32>(grid.dims) - 1);
+
+    var res = 0.;
+    for (var z = lo.z; z <= hi.z; z++) {
+        for (var y = lo.y; y <= hi.y; y++) {
+            for (var x = lo.x; x <= hi.x; x++) {
+                let cell = grid_cell_index(vec3<i32>(x, y, z));
+                let start = cell_starts[cell];
+                for (var k = start; k < start + cell_counts[cell]; k++) {
+                    if !selected(sorted_indices[k], particles) {
+                        continue;
+                    }
+                    let dist = distance(positions[sorted_indices[k]].xyz, pos);
+                    if dist < grid.cutoff {
+                        res += exp(alpha * dist);
+                    }
+                }
+            }
+        }
+    }
+    return log(res) / alpha;
+}
+
+// * Same as log_sum_exp_grad, but only over particles within grid.cutoff of a world space position
+fn log_sum_exp_grad_grid(pos: vec3<f32>, alpha: f32, particles: u32) -> vec3<f32> {
+    let lo = max(grid_cell(pos - grid.cutoff), vec3<i32>(0));
+    let hi = min(grid_cell(pos + grid.cutoff), vec3<i32>(grid.dims) - 1);
+
+    var sum_exp = 0.;
+    var weighted_grad = vec3<f32>(0., 0., 0.);
+    for (var z = lo.z; z <= hi.z; z++) {
+        for (var y = lo.y; y <= hi.y; y++) {
+            for (var x = lo.x; x <= hi.x; x++) {
+                let cell = grid_cell_index(vec3<i32>(x, y, z));
+                let start = cell_starts[cell];
+                for (var k = start; k < start + cell_counts[cell]; k++) {
+                    if !selected(sorted_indices[k], particles) {
+                        continue;
+                    }
+                    let particle = positions[sorted_indices[k]].xyz;
+                    let dist = distance(particle, pos);
+                    if dist < grid.cutoff {
+                        let weight = exp(alpha * dist);
+                        sum_exp += weight;
+                        weighted_grad += weight * -normalize(particle - pos);
+                    }
+                }
+            }
+        }
+    }
+    return weighted_grad / sum_exp;
+}
+
+// * Same as log_sum_exp_scalar, but only over particles within grid.cutoff of a world space position
+fn log_sum_exp_scalar_grid(pos: vec3<f32>, alpha: f32, particles: u32) -> f32 {
+    let lo = max(grid_cell(pos - grid.cutoff), vec3<i32>(0));
+    let hi = min(grid_cell(pos + grid.cutoff), vec3<i32>(grid.dims) - 1);
+
+    var sum_exp = 0.;
+    var weighted_sum = 0.;
+    for (var z = lo.z; z <= hi.z; z++) {
+        for (var y = lo.y; y <= hi.y; y++) {
+            for (var x = lo.x; x <= hi.x; x++) {
+                let cell = grid_cell_index(vec3<i32>(x, y, z));
+                let start = cell_starts[cell];
+                for (var k = start; k < start + cell_counts[cell]; k++) {
+                    let i = sorted_indices[k];
+                    if !selected(i, particles) {
+                        continue;
+                    }
+                    let dist = distance(positions[i].xyz, pos);
+                    if dist < grid.cutoff {
+                        let weight = exp(alpha * dist);
+                        sum_exp += weight;
+                        weighted_sum += weight * scalars.values[i];
+                    }
+                }
+            }
+        }
+    }
+    return weighted_sum / sum_exp;
+}
+
 // pub fn log_sum_exp_grad(points: &[DVec2], alpha: f64) -> DVec2 {
 //     let distances = points.iter().map(|v| v.length());
 //     let sum_exp: f64 = distances.clone().map(|v| (v * alpha).exp()).sum();
@@ -110,4 +612,4 @@ fn log_sum_exp_grad(ray_pos: vec3<f32>, view_matrix: mat4x4<f32>, alpha: f32) ->
 //         .map(|(pd, dg)| dg * pd)
 //         .fold(DVec2::ZERO, |a, v| a + v);
 //     gradient
-// }
\ No newline at end of file
+// }
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use wgpu::{
     Backends, Device, Instance, InstanceDescriptor, Queue, Surface, SurfaceConfiguration,
     TextureUsages,
@@ -10,8 +12,8 @@ use winit::{
 
 pub struct Environment {
     pub surface: Surface,
-    pub device: Device,
-    pub queue: Queue,
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
     pub config: SurfaceConfiguration,
     pub window: Window,
     pub cursor_grab: bool,
@@ -73,11 +75,27 @@ impl Environment {
 
         Self {
             surface,
-            device,
-            queue,
+            device: Arc::new(device),
+            queue: Arc::new(queue),
             config,
             window,
             cursor_grab: false,
         }
     }
+
+    // * Reconfigures the surface for a new window size, false if the window is minimised
+    pub fn resize(&mut self, size: PhysicalSize<u32>) -> bool {
+        if size.width == 0 || size.height == 0 {
+            return false;
+        }
+        self.config.width = size.width;
+        self.config.height = size.height;
+        self.surface.configure(&self.device, &self.config);
+        true
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        let size = self.window.inner_size();
+        size.width == 0 || size.height == 0
+    }
 }
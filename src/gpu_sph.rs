@@ -0,0 +1,582 @@
+use std::sync::{mpsc, Arc};
+
+use glam::Vec3;
+use sph::sph::Particle;
+use wgpu::{
+    include_wgsl, util::DeviceExt, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutEntry, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor,
+    Device, PipelineLayoutDescriptor, Queue, ShaderStages,
+};
+
+use crate::{
+    backend::{BackendContext, SimulationBackend, SphBackend},
+    config::DEFAULT_DELTA_TIME,
+    grid::GridState,
+    headless::HeadlessEnvironment,
+    scene::{FluidParams, Scene, SolverKind},
+    simulation::{particle_flags, SimulationCommand, SimulationState, RENDER_SCALE},
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+// * Free space around the initial particles before the domain walls, in smoothing lengths
+const DOMAIN_MARGIN: f32 = 4.;
+// * Courant number for the substep size
+const CFL: f32 = 0.25;
+// * Largest accepted deviation from the CPU reference, in simulation units
+const CROSS_CHECK_TOLERANCE: f32 = 1.0e-3;
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, Default)]
+struct SphParams {
+    domain_min: Vec3,
+    smoothing_length: f32,
+    domain_max: Vec3,
+    dt: f32,
+    gravity: Vec3,
+    rest_density: f32,
+    stiffness: f32,
+    viscosity: f32,
+    num_particles: u32,
+    _padding: u32,
+}
+
+impl SphParams {
+    fn new(fluid: &FluidParams, gravity: Vec3, particles: &[Particle]) -> Self {
+        let (min, max) = particles.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), p| (min.min(p.pos.into()), max.max(p.pos.into())),
+        );
+        let margin = DOMAIN_MARGIN * fluid.smoothing_length;
+        Self {
+            domain_min: min - margin,
+            smoothing_length: fluid.smoothing_length,
+            domain_max: max + margin,
+            dt: DEFAULT_DELTA_TIME / Self::substeps(fluid, DEFAULT_DELTA_TIME) as f32,
+            gravity,
+            rest_density: fluid.rest_density,
+            stiffness: fluid.stiffness,
+            viscosity: fluid.viscosity,
+            num_particles: particles.len() as u32,
+            _padding: 0,
+        }
+    }
+
+    // * Substeps per step of `dt` so that the explicit integration stays stable
+    fn substeps(fluid: &FluidParams, dt: f32) -> u32 {
+        let h = fluid.smoothing_length;
+        let sound_speed = fluid.stiffness.sqrt();
+        let mut max_dt = CFL * h / sound_speed;
+        if fluid.viscosity > 0. {
+            max_dt = max_dt.min(0.125 * h * h / fluid.viscosity);
+        }
+        (dt / max_dt).ceil().max(1.) as u32
+    }
+
+    fn support(&self) -> f32 {
+        2. * self.smoothing_length
+    }
+}
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+struct ParticleInfo {
+    mass: f32,
+    movable: u32,
+}
+
+struct SphPipelines {
+    density: ComputePipeline,
+    forces: ComputePipeline,
+    integrate: ComputePipeline,
+}
+
+// * SPH solver running entirely in compute shaders. It steps the positions in place in the
+// * simulation buffer the renderer reads, they are only read back for the CPU side.
+pub struct GpuSph {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    fluid: FluidParams,
+    params: SphParams,
+    params_buffer: Buffer,
+    positions: Arc<Buffer>,
+    velocities: Buffer,
+    densities: Buffer,
+    infos: Buffer,
+    bind_group: BindGroup,
+    // * Read only view of the positions for the grid binning
+    simulation_bind_group: BindGroup,
+    // * Neighbour search, cells of one kernel support fitted to the domain
+    grid: GridState,
+    pipelines: SphPipelines,
+    readback: Buffer,
+}
+
+impl GpuSph {
+    pub fn new(
+        context: &BackendContext,
+        fluid: &FluidParams,
+        gravity: Vec3,
+        particles: Vec<Particle>,
+    ) -> Self {
+        let device = context.device;
+        let num_particles = particles.len() as u32;
+        let params = SphParams::new(fluid, gravity, &particles);
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SPH Params Buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let vec4_size = std::mem::size_of::<[f32; 4]>();
+        let particle_buffer = |label, stride: usize| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: (num_particles.max(1) as usize * stride) as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        };
+        let velocities = particle_buffer("SPH Velocities", vec4_size);
+        let accelerations = particle_buffer("SPH Accelerations", vec4_size);
+        let densities = particle_buffer("SPH Densities", std::mem::size_of::<f32>());
+        let infos = particle_buffer("SPH Particle Infos", std::mem::size_of::<ParticleInfo>());
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("SPH Readback Buffer"),
+            size: (num_particles.max(1) as usize * vec4_size) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let entry = |binding, ty| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = |read_only| BufferBindingType::Storage { read_only };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SPH Bind Group Layout"),
+            entries: &[
+                entry(0, BufferBindingType::Uniform),
+                entry(1, storage(false)),
+                entry(2, storage(false)),
+                entry(3, storage(false)),
+                entry(4, storage(false)),
+                entry(5, storage(true)),
+            ],
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("SPH Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                params_buffer.as_entire_binding(),
+                context.buffer.as_entire_binding(),
+                velocities.as_entire_binding(),
+                accelerations.as_entire_binding(),
+                densities.as_entire_binding(),
+                infos.as_entire_binding(),
+            ]
+            .into_iter()
+            .enumerate()
+            .map(|(binding, resource)| BindGroupEntry {
+                binding: binding as u32,
+                resource,
+            })
+            .collect::<Vec<_>>(),
+        });
+        let simulation_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("SPH Simulation Bind Group"),
+            layout: context.simulation_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: context.buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: context.scalar_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let (grid, grid_bind_group_layout) =
+            GridState::create_grid(device, context.simulation_bind_group_layout, num_particles);
+        let pipelines =
+            Self::create_pipelines(device, &[&bind_group_layout, &grid_bind_group_layout]);
+
+        let mut gpu_sph = Self {
+            device: device.clone(),
+            queue: context.queue.clone(),
+            fluid: *fluid,
+            params,
+            params_buffer,
+            positions: context.buffer.clone(),
+            velocities,
+            densities,
+            infos,
+            bind_group,
+            simulation_bind_group,
+            grid,
+            pipelines,
+            readback,
+        };
+        gpu_sph.reset(particles);
+        gpu_sph
+    }
+
+    fn create_pipelines(device: &Device, bind_group_layouts: &[&BindGroupLayout]) -> SphPipelines {
+        // * LOAD SHADER
+        let sph_shader = device.create_shader_module(include_wgsl!("gpu_sph.wgsl"));
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("SPH Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &sph_shader,
+                entry_point,
+            })
+        };
+        SphPipelines {
+            density: pipeline("compute_density"),
+            forces: pipeline("compute_forces"),
+            integrate: pipeline("integrate"),
+        }
+    }
+
+    // * Copies the first `size` bytes of `buffer` back to the CPU, waiting for the GPU
+    fn read_back<T: bytemuck::Pod>(&self, buffer: &Buffer, size: u64) -> Vec<T> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("SPH Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(buffer, 0, &self.readback, 0, size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback.slice(..size);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .unwrap()
+            .expect("failed to map SPH readback buffer");
+        let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        self.readback.unmap();
+        data
+    }
+
+    fn read_vec4(&self, buffer: &Buffer) -> Vec<Vec3> {
+        let size = self.params.num_particles as u64 * std::mem::size_of::<[f32; 4]>() as u64;
+        self.read_back::<[f32; 4]>(buffer, size)
+            .into_iter()
+            .map(|v| Vec3::new(v[0], v[1], v[2]))
+            .collect()
+    }
+}
+
+impl SimulationBackend for GpuSph {
+    fn step(&mut self, dt: f32) {
+        let substeps = SphParams::substeps(&self.fluid, dt);
+        if self.params.dt * substeps as f32 != dt {
+            self.params.dt = dt / substeps as f32;
+            self.queue
+                .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+        }
+
+        let particle_groups = self.params.num_particles.div_ceil(WORKGROUP_SIZE);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("SPH Encoder"),
+            });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("SPH Pass"),
+            });
+            for _ in 0..substeps {
+                self.grid
+                    .encode_binning(&mut compute_pass, &self.simulation_bind_group);
+
+                compute_pass.set_bind_group(0, &self.bind_group, &[]);
+                compute_pass.set_bind_group(1, &self.grid.bind_group, &[]);
+                for pipeline in [
+                    &self.pipelines.density,
+                    &self.pipelines.forces,
+                    &self.pipelines.integrate,
+                ] {
+                    compute_pass.set_pipeline(pipeline);
+                    compute_pass.dispatch_workgroups(particle_groups, 1, 1);
+                }
+            }
+        }
+        self.queue.submit(Some(encoder.finish()));
+    }
+
+    fn positions(&self) -> Vec<Vec3> {
+        self.read_vec4(&self.positions)
+            .into_iter()
+            .map(|p| p / RENDER_SCALE)
+            .collect()
+    }
+
+    fn velocities(&self) -> Option<Vec<Vec3>> {
+        Some(self.read_vec4(&self.velocities))
+    }
+
+    fn densities(&self) -> Option<Vec<f32>> {
+        let size = self.params.num_particles as u64 * std::mem::size_of::<f32>() as u64;
+        Some(self.read_back(&self.densities, size))
+    }
+
+    // * Same equation of state as gpu_sph.wgsl
+    fn pressures(&self) -> Option<Vec<f32>> {
+        let params = &self.params;
+        let densities = self.densities()?;
+        Some(
+            densities
+                .into_iter()
+                .map(|d| (params.stiffness * (d - params.rest_density)).max(0.))
+                .collect(),
+        )
+    }
+
+    fn num_particles(&self) -> usize {
+        self.params.num_particles as usize
+    }
+
+    // * Uploads all particle data and refits the domain
+    fn reset(&mut self, particles: Vec<Particle>) {
+        self.params = SphParams::new(&self.fluid, self.params.gravity, &particles);
+        self.queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+
+        let positions: Vec<[f32; 4]> = particles
+            .iter()
+            .map(|p| {
+                (Vec3::from(p.pos) * RENDER_SCALE)
+                    .extend(particle_flags(p.movable))
+                    .to_array()
+            })
+            .collect();
+        self.queue
+            .write_buffer(&self.positions, 0, bytemuck::cast_slice(&positions));
+        let velocities: Vec<[f32; 4]> = particles
+            .iter()
+            .map(|p| Vec3::from(p.vel).extend(0.).to_array())
+            .collect();
+        self.queue
+            .write_buffer(&self.velocities, 0, bytemuck::cast_slice(&velocities));
+        let infos: Vec<ParticleInfo> = particles
+            .iter()
+            .map(|p| ParticleInfo {
+                mass: p.mass,
+                movable: p.movable as u32,
+            })
+            .collect();
+        self.queue
+            .write_buffer(&self.infos, 0, bytemuck::cast_slice(&infos));
+
+        self.grid.fit(
+            &self.queue,
+            self.params.domain_min * RENDER_SCALE,
+            self.params.domain_max * RENDER_SCALE,
+            self.params.support() * RENDER_SCALE,
+        );
+    }
+
+    fn writes_buffer(&self) -> bool {
+        true
+    }
+
+    // * The integration clamps every particle to the domain
+    fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        Some((self.params.domain_min, self.params.domain_max))
+    }
+}
+
+// * The same scheme as gpu_sph.wgsl, brute force on the CPU, for cross-checking the shaders
+struct ReferenceSph {
+    params: SphParams,
+    substeps: u32,
+    positions: Vec<Vec3>,
+    velocities: Vec<Vec3>,
+    masses: Vec<f32>,
+    movable: Vec<bool>,
+}
+
+impl ReferenceSph {
+    fn new(scene: &Scene) -> Self {
+        let particles = scene.particles();
+        Self {
+            params: SphParams::new(&scene.fluid, scene.gravity.into(), &particles),
+            substeps: SphParams::substeps(&scene.fluid, DEFAULT_DELTA_TIME),
+            positions: particles.iter().map(|p| p.pos.into()).collect(),
+            velocities: particles.iter().map(|p| p.vel.into()).collect(),
+            masses: particles.iter().map(|p| p.mass).collect(),
+            movable: particles.iter().map(|p| p.movable).collect(),
+        }
+    }
+
+    fn kernel(&self, r: f32) -> f32 {
+        let h = self.params.smoothing_length;
+        let q = r / h;
+        let sigma = 1. / (std::f32::consts::PI * h * h * h);
+        if q < 1. {
+            sigma * (1. - 1.5 * q * q + 0.75 * q * q * q)
+        } else if q < 2. {
+            sigma * 0.25 * (2. - q).powi(3)
+        } else {
+            0.
+        }
+    }
+
+    fn kernel_gradient(&self, r_vec: Vec3, r: f32) -> Vec3 {
+        let h = self.params.smoothing_length;
+        let q = r / h;
+        let sigma = 1. / (std::f32::consts::PI * h * h * h);
+        let dw = if q < 1. {
+            sigma / h * (-3. * q + 2.25 * q * q)
+        } else if q < 2. {
+            sigma / h * (-0.75 * (2. - q).powi(2))
+        } else {
+            0.
+        };
+        if r < 1.0e-6 {
+            Vec3::ZERO
+        } else {
+            dw * r_vec / r
+        }
+    }
+
+    fn pressure(&self, density: f32) -> f32 {
+        (self.params.stiffness * (density - self.params.rest_density)).max(0.)
+    }
+
+    fn step(&mut self) {
+        let n = self.positions.len();
+        let h = self.params.smoothing_length;
+        for _ in 0..self.substeps {
+            let densities: Vec<f32> = (0..n)
+                .map(|i| {
+                    (0..n)
+                        .map(|j| {
+                            self.masses[j]
+                                * self.kernel(self.positions[i].distance(self.positions[j]))
+                        })
+                        .sum()
+                })
+                .collect();
+            let accelerations: Vec<Vec3> = (0..n)
+                .map(|i| {
+                    let pressure_term = self.pressure(densities[i]) / densities[i].powi(2);
+                    let mut acc = self.params.gravity;
+                    let neighbours = self.positions.iter().zip(&self.velocities);
+                    let neighbour_data = self.masses.iter().zip(&densities);
+                    for (j, ((pos_j, vel_j), (mass_j, density_j))) in
+                        neighbours.zip(neighbour_data).enumerate()
+                    {
+                        let r_vec = self.positions[i] - *pos_j;
+                        let r = r_vec.length();
+                        if j == i || r >= self.params.support() {
+                            continue;
+                        }
+                        let grad = self.kernel_gradient(r_vec, r);
+                        let pressure_j = self.pressure(*density_j) / density_j.powi(2);
+                        acc -= mass_j * (pressure_term + pressure_j) * grad;
+                        let v_ij = self.velocities[i] - *vel_j;
+                        acc += 2. * self.params.viscosity * mass_j / density_j
+                            * v_ij
+                            * r_vec.dot(grad)
+                            / (r * r + 0.01 * h * h);
+                    }
+                    acc
+                })
+                .collect();
+            for i in (0..n).filter(|&i| self.movable[i]) {
+                let mut vel = self.velocities[i] + self.params.dt * accelerations[i];
+                let pos = self.positions[i] + self.params.dt * vel;
+                let clamped = pos.clamp(self.params.domain_min, self.params.domain_max);
+                vel = Vec3::select(clamped.cmpne(pos), Vec3::ZERO, vel);
+                self.velocities[i] = vel;
+                self.positions[i] = clamped;
+            }
+        }
+    }
+}
+
+// * Removes `--cross-check <steps>` from the arguments
+pub fn take_cross_check_arg(args: &mut Vec<String>) -> Result<Option<u32>, String> {
+    let Some(i) = args.iter().position(|arg| arg == "--cross-check") else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err("missing value for --cross-check".into());
+    }
+    let steps = args
+        .remove(i + 1)
+        .parse()
+        .map_err(|e| format!("invalid --cross-check: {e}"))?;
+    args.remove(i);
+    Ok(Some(steps))
+}
+
+// * Runs the scene on the GPU solver, the CPU reference of the same scheme and the `sph` crate,
+// * and reports how far the GPU positions are from the other two. Meant for small scenes.
+pub fn cross_check(scene: &Scene, steps: u32) -> Result<(), String> {
+    let env = pollster::block_on(HeadlessEnvironment::new(1, 1))?;
+    let mut gpu_scene = scene.clone();
+    gpu_scene.solver = SolverKind::Gpu;
+    let (mut simulation_state, _) =
+        SimulationState::create_simulation(&gpu_scene, &env.device, &env.queue)?;
+    let mut reference = ReferenceSph::new(scene);
+    let mut sph = SphBackend::new(scene.particles(), scene.fluid, scene.gravity.into());
+
+    for _ in 0..steps {
+        // * One at a time, queued steps beyond the substep cap would be dropped
+        simulation_state.send(SimulationCommand::Step);
+        simulation_state.sync(&env.queue);
+        reference.step();
+        sph.step(DEFAULT_DELTA_TIME);
+    }
+
+    let gpu: Vec<Vec3> = simulation_state
+        .positions()
+        .iter()
+        .map(|p| *p / RENDER_SCALE)
+        .collect();
+    let deviation = |other: &[Vec3]| {
+        let distances: Vec<f32> = gpu.iter().zip(other).map(|(a, b)| a.distance(*b)).collect();
+        let max = distances.iter().copied().fold(0., f32::max);
+        let rms = (distances.iter().map(|d| d * d).sum::<f32>() / distances.len() as f32).sqrt();
+        (max, rms)
+    };
+
+    let (max, rms) = deviation(&reference.positions);
+    println!(
+        "{} particles, {steps} steps: gpu vs cpu reference: max {max:e}, rms {rms:e}",
+        gpu.len()
+    );
+    let (sph_max, sph_rms) = deviation(&sph.positions());
+    // * Different discretisation, only expected to agree qualitatively
+    println!("gpu vs sph crate: max {sph_max:e}, rms {sph_rms:e}");
+
+    if max <= CROSS_CHECK_TOLERANCE {
+        Ok(())
+    } else {
+        Err(format!(
+            "gpu solver deviates from the cpu reference by {max:e} (tolerance {CROSS_CHECK_TOLERANCE:e})"
+        ))
+    }
+}
@@ -0,0 +1,192 @@
+// * Weakly compressible SPH on the GPU, one substep is
+// * grid binning (grid.wgsl) -> compute_density -> compute_forces -> integrate
+// * Positions live in render space (simulation units * RENDER_SCALE) in the buffer the
+// * renderer reads, everything else is in simulation units.
+
+const RENDER_SCALE: f32 = 10.;
+const PI: f32 = 3.14159265;
+
+struct Params {
+    domain_min: vec3<f32>,
+    smoothing_length: f32,
+    domain_max: vec3<f32>,
+    dt: f32,
+    gravity: vec3<f32>,
+    rest_density: f32,
+    stiffness: f32,
+    viscosity: f32,
+    num_particles: u32,
+}
+
+struct ParticleInfo {
+    mass: f32,
+    movable: u32,
+}
+
+struct Grid {
+    origin: vec3<f32>,
+    cell_size: f32,
+    dims: vec3<u32>,
+    cutoff: f32,
+    num_particles: u32,
+    enabled: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+@group(0) @binding(1)
+var<storage, read_write> positions: array<vec4<f32>>;
+@group(0) @binding(2)
+var<storage, read_write> velocities: array<vec4<f32>>;
+@group(0) @binding(3)
+var<storage, read_write> accelerations: array<vec4<f32>>;
+@group(0) @binding(4)
+var<storage, read_write> densities: array<f32>;
+@group(0) @binding(5)
+var<storage, read> infos: array<ParticleInfo>;
+
+@group(1) @binding(0)
+var<uniform> grid: Grid;
+@group(1) @binding(1)
+var<storage, read> cell_counts: array<u32>;
+@group(1) @binding(2)
+var<storage, read> cell_starts: array<u32>;
+@group(1) @binding(3)
+var<storage, read> sorted_indices: array<u32>;
+
+// * Cubic spline with support 2h
+fn kernel(r: f32) -> f32 {
+    let h = params.smoothing_length;
+    let q = r / h;
+    let sigma = 1. / (PI * h * h * h);
+    if q < 1. {
+        return sigma * (1. - 1.5 * q * q + 0.75 * q * q * q);
+    } else if q < 2. {
+        let t = 2. - q;
+        return sigma * 0.25 * t * t * t;
+    }
+    return 0.;
+}
+
+fn kernel_gradient(r_vec: vec3<f32>, r: f32) -> vec3<f32> {
+    let h = params.smoothing_length;
+    let q = r / h;
+    let sigma = 1. / (PI * h * h * h);
+    var dw = 0.;
+    if q < 1. {
+        dw = sigma / h * (-3. * q + 2.25 * q * q);
+    } else if q < 2. {
+        let t = 2. - q;
+        dw = sigma / h * (-0.75 * t * t);
+    }
+    if r < 1.0e-6 {
+        return vec3<f32>(0.);
+    }
+    return dw * r_vec / r;
+}
+
+fn position(i: u32) -> vec3<f32> {
+    return positions[i].xyz / RENDER_SCALE;
+}
+
+fn pressure(density: f32) -> f32 {
+    return max(params.stiffness * (density - params.rest_density), 0.);
+}
+
+fn grid_cell(pos: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor((pos * RENDER_SCALE - grid.origin) / grid.cell_size));
+}
+
+fn grid_cell_index(cell: vec3<i32>) -> u32 {
+    return u32(cell.x) + grid.dims.x * (u32(cell.y) + grid.dims.y * u32(cell.z));
+}
+
+@compute @workgroup_size(64)
+fn compute_density(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= params.num_particles {
+        return;
+    }
+    let pos = position(i);
+    let support = 2. * params.smoothing_length;
+    let lo = max(grid_cell(pos - support), vec3<i32>(0));
+    let hi = min(grid_cell(pos + support), vec3<i32>(grid.dims) - 1);
+
+    var density = 0.;
+    for (var z = lo.z; z <= hi.z; z++) {
+        for (var y = lo.y; y <= hi.y; y++) {
+            for (var x = lo.x; x <= hi.x; x++) {
+                let cell = grid_cell_index(vec3<i32>(x, y, z));
+                let start = cell_starts[cell];
+                for (var k = start; k < start + cell_counts[cell]; k++) {
+                    let j = sorted_indices[k];
+                    density += infos[j].mass * kernel(distance(pos, position(j)));
+                }
+            }
+        }
+    }
+    densities[i] = density;
+}
+
+@compute @workgroup_size(64)
+fn compute_forces(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= params.num_particles {
+        return;
+    }
+    let pos = position(i);
+    let vel = velocities[i].xyz;
+    let density = densities[i];
+    let pressure_term = pressure(density) / (density * density);
+    let h = params.smoothing_length;
+    let support = 2. * h;
+    let lo = max(grid_cell(pos - support), vec3<i32>(0));
+    let hi = min(grid_cell(pos + support), vec3<i32>(grid.dims) - 1);
+
+    var acc = params.gravity;
+    for (var z = lo.z; z <= hi.z; z++) {
+        for (var y = lo.y; y <= hi.y; y++) {
+            for (var x = lo.x; x <= hi.x; x++) {
+                let cell = grid_cell_index(vec3<i32>(x, y, z));
+                let start = cell_starts[cell];
+                for (var k = start; k < start + cell_counts[cell]; k++) {
+                    let j = sorted_indices[k];
+                    let r_vec = pos - position(j);
+                    let r = length(r_vec);
+                    if j == i || r >= support {
+                        continue;
+                    }
+                    let grad = kernel_gradient(r_vec, r);
+                    let density_j = densities[j];
+                    let mass_j = infos[j].mass;
+                    // * Symmetric pressure force
+                    let pressure_j = pressure(density_j) / (density_j * density_j);
+                    acc -= mass_j * (pressure_term + pressure_j) * grad;
+                    // * Morris viscosity
+                    let v_ij = vel - velocities[j].xyz;
+                    acc += 2. * params.viscosity * mass_j / density_j * v_ij
+                        * dot(r_vec, grad) / (r * r + 0.01 * h * h);
+                }
+            }
+        }
+    }
+    accelerations[i] = vec4<f32>(acc, 0.);
+}
+
+@compute @workgroup_size(64)
+fn integrate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= params.num_particles || infos[i].movable == 0u {
+        return;
+    }
+    var vel = velocities[i].xyz + params.dt * accelerations[i].xyz;
+    var pos = position(i) + params.dt * vel;
+
+    // * Walls of the simulation domain, the normal velocity is removed
+    let clamped = clamp(pos, params.domain_min, params.domain_max);
+    vel = select(vel, vec3<f32>(0.), clamped != pos);
+    pos = clamped;
+
+    velocities[i] = vec4<f32>(vel, 0.);
+    positions[i] = vec4<f32>(pos * RENDER_SCALE, positions[i].w);
+}
@@ -0,0 +1,297 @@
+use glam::{UVec3, Vec3};
+use wgpu::{
+    include_wgsl, util::DeviceExt, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutEntry, Buffer, BufferBindingType, BufferDescriptor, BufferUsages,
+    CommandEncoderDescriptor, ComputePass, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, Device, PipelineLayoutDescriptor, Queue, ShaderStages,
+};
+
+use crate::{config::Config, simulation::SimulationState};
+
+// * UPPER BOUND OF CELLS PER AXIS, THE CELL SIZE GROWS INSTEAD
+const MAX_GRID_DIM: u32 = 64;
+const MAX_CELLS: u32 = MAX_GRID_DIM * MAX_GRID_DIM * MAX_GRID_DIM;
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, Default)]
+pub struct GridUniform {
+    origin: Vec3,
+    cell_size: f32,
+    dims: UVec3,
+    cutoff: f32,
+    num_particles: u32,
+    enabled: u32,
+    _padding: [u32; 2],
+}
+
+struct GridPipelines {
+    clear: ComputePipeline,
+    count: ComputePipeline,
+    prefix_sum: ComputePipeline,
+    scatter: ComputePipeline,
+}
+
+// * Uniform grid over the particle positions, rebuilt on the GPU after every simulation step.
+// * When disabled the draw shader falls back to summing over all particles.
+pub struct GridState {
+    pub uniform: GridUniform,
+    pub buffer: Buffer,
+    pub compute_bind_group: BindGroup,
+    pub bind_group: BindGroup,
+    pipelines: GridPipelines,
+}
+
+impl GridState {
+    pub fn create_grid(
+        device: &Device,
+        simulation_bind_group_layout: &BindGroupLayout,
+        num_particles: u32,
+    ) -> (Self, BindGroupLayout) {
+        let uniform = GridUniform {
+            num_particles,
+            enabled: 1,
+            ..Default::default()
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let cell_buffer = |label| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: (MAX_CELLS as usize * std::mem::size_of::<u32>()) as u64,
+                usage: BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            })
+        };
+        let counts = cell_buffer("Grid Cell Counts");
+        let starts = cell_buffer("Grid Cell Starts");
+        let offsets = cell_buffer("Grid Cell Offsets");
+        let sorted_indices = device.create_buffer(&BufferDescriptor {
+            label: Some("Grid Sorted Indices"),
+            size: (num_particles.max(1) as usize * std::mem::size_of::<u32>()) as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        // * COMPUTE BIND GROUP (writes the grid)
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Grid Compute Bind Group Layout"),
+                entries: &[
+                    Self::layout_entry(0, ShaderStages::COMPUTE, BufferBindingType::Uniform),
+                    Self::storage_entry(1, ShaderStages::COMPUTE, false),
+                    Self::storage_entry(2, ShaderStages::COMPUTE, false),
+                    Self::storage_entry(3, ShaderStages::COMPUTE, false),
+                    Self::storage_entry(4, ShaderStages::COMPUTE, false),
+                ],
+            });
+        let compute_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Grid Compute Bind Group"),
+            layout: &compute_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: counts.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: starts.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: offsets.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: sorted_indices.as_entire_binding(),
+                },
+            ],
+        });
+
+        // * RENDER BIND GROUP (reads the grid, also used by the marching cubes pass)
+        let visibility = ShaderStages::FRAGMENT | ShaderStages::COMPUTE;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid Bind Group Layout"),
+            entries: &[
+                Self::layout_entry(0, visibility, BufferBindingType::Uniform),
+                Self::storage_entry(1, visibility, true),
+                Self::storage_entry(2, visibility, true),
+                Self::storage_entry(3, visibility, true),
+            ],
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Grid Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: counts.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: starts.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: sorted_indices.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipelines = Self::create_pipelines(
+            device,
+            &[simulation_bind_group_layout, &compute_bind_group_layout],
+        );
+
+        (
+            Self {
+                uniform,
+                buffer,
+                compute_bind_group,
+                bind_group,
+                pipelines,
+            },
+            bind_group_layout,
+        )
+    }
+
+    fn layout_entry(
+        binding: u32,
+        visibility: ShaderStages,
+        ty: BufferBindingType,
+    ) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    fn storage_entry(
+        binding: u32,
+        visibility: ShaderStages,
+        read_only: bool,
+    ) -> BindGroupLayoutEntry {
+        Self::layout_entry(
+            binding,
+            visibility,
+            BufferBindingType::Storage { read_only },
+        )
+    }
+
+    fn create_pipelines(device: &Device, bind_group_layouts: &[&BindGroupLayout]) -> GridPipelines {
+        // * LOAD SHADER
+        let grid_shader = device.create_shader_module(include_wgsl!("grid.wgsl"));
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &grid_shader,
+                entry_point,
+            })
+        };
+        GridPipelines {
+            clear: pipeline("clear_cells"),
+            count: pipeline("count_particles"),
+            prefix_sum: pipeline("prefix_sum"),
+            scatter: pipeline("scatter_particles"),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool, queue: &Queue) {
+        self.uniform.enabled = enabled as u32;
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.uniform));
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.uniform.enabled != 0
+    }
+
+    // * Fits the grid around the current particle bounds and bins the particles on the GPU.
+    pub fn rebuild(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        simulation_state: &SimulationState,
+        config: &Config,
+    ) {
+        let cutoff = config.influence_range();
+        let (min, max) = match simulation_state.bounds() {
+            Some((min, max)) => (min - cutoff, max + cutoff),
+            None => (Vec3::ZERO, Vec3::ZERO),
+        };
+        self.fit(queue, min, max, cutoff);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Grid Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Grid Pass"),
+            });
+            self.encode_binning(&mut compute_pass, &simulation_state.bind_group);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+
+    // * Covers the box from `min` to `max` with cells of at least `cutoff`
+    pub fn fit(&mut self, queue: &Queue, min: Vec3, max: Vec3, cutoff: f32) {
+        let extent = max - min;
+        let cell_size = cutoff.max(extent.max_element() / MAX_GRID_DIM as f32);
+        let dims = (extent / cell_size)
+            .ceil()
+            .as_uvec3()
+            .clamp(UVec3::ONE, UVec3::splat(MAX_GRID_DIM));
+
+        self.uniform.origin = min;
+        self.uniform.cell_size = cell_size;
+        self.uniform.dims = dims;
+        self.uniform.cutoff = cutoff;
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&self.uniform));
+    }
+
+    // * Bins the particles into the fitted cells, e.g. once per solver substep
+    pub fn encode_binning<'a>(
+        &'a self,
+        compute_pass: &mut ComputePass<'a>,
+        simulation_bind_group: &'a BindGroup,
+    ) {
+        let dims = self.uniform.dims;
+        let particle_groups = self.uniform.num_particles.div_ceil(WORKGROUP_SIZE);
+        let cell_groups = (dims.x * dims.y * dims.z).div_ceil(WORKGROUP_SIZE);
+
+        compute_pass.set_bind_group(0, simulation_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.compute_bind_group, &[]);
+
+        compute_pass.set_pipeline(&self.pipelines.clear);
+        compute_pass.dispatch_workgroups(cell_groups, 1, 1);
+        compute_pass.set_pipeline(&self.pipelines.count);
+        compute_pass.dispatch_workgroups(particle_groups, 1, 1);
+        compute_pass.set_pipeline(&self.pipelines.prefix_sum);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+        compute_pass.set_pipeline(&self.pipelines.scatter);
+        compute_pass.dispatch_workgroups(particle_groups, 1, 1);
+    }
+}
@@ -0,0 +1,96 @@
+// * Bins the particle positions into a uniform grid so the ray marcher only has
+// * to look at the cells around a sample point instead of every particle.
+// * Passes: clear_cells -> count_particles -> prefix_sum -> scatter_particles
+
+const PREFIX_SUM_THREADS: u32 = 256u;
+
+struct Grid {
+    origin: vec3<f32>,
+    cell_size: f32,
+    dims: vec3<u32>,
+    cutoff: f32,
+    num_particles: u32,
+    enabled: u32,
+}
+
+@group(0) @binding(0)
+var<storage, read> positions: array<vec3<f32>>;
+
+@group(1) @binding(0)
+var<uniform> grid: Grid;
+@group(1) @binding(1)
+var<storage, read_write> cell_counts: array<atomic<u32>>;
+@group(1) @binding(2)
+var<storage, read_write> cell_starts: array<u32>;
+@group(1) @binding(3)
+var<storage, read_write> cell_offsets: array<atomic<u32>>;
+@group(1) @binding(4)
+var<storage, read_write> sorted_indices: array<u32>;
+
+var<workgroup> partial_sums: array<u32, PREFIX_SUM_THREADS>;
+
+fn total_cells() -> u32 {
+    return grid.dims.x * grid.dims.y * grid.dims.z;
+}
+
+fn cell_index(pos: vec3<f32>) -> u32 {
+    let max_cell = vec3<i32>(grid.dims) - 1;
+    let cell = clamp(vec3<i32>(floor((pos - grid.origin) / grid.cell_size)), vec3<i32>(0), max_cell);
+    return u32(cell.x) + grid.dims.x * (u32(cell.y) + grid.dims.y * u32(cell.z));
+}
+
+@compute @workgroup_size(64)
+fn clear_cells(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x < total_cells() {
+        atomicStore(&cell_counts[id.x], 0u);
+    }
+}
+
+@compute @workgroup_size(64)
+fn count_particles(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x < grid.num_particles {
+        atomicAdd(&cell_counts[cell_index(positions[id.x])], 1u);
+    }
+}
+
+// * Exclusive scan over all cell counts, dispatched as a single workgroup.
+// * Every thread scans a contiguous chunk of cells, the chunk totals are scanned in between.
+@compute @workgroup_size(256)
+fn prefix_sum(@builtin(local_invocation_index) lid: u32) {
+    let n = total_cells();
+    let chunk = (n + PREFIX_SUM_THREADS - 1u) / PREFIX_SUM_THREADS;
+    let begin = min(lid * chunk, n);
+    let end = min(begin + chunk, n);
+
+    var sum = 0u;
+    for (var c = begin; c < end; c++) {
+        sum += atomicLoad(&cell_counts[c]);
+    }
+    partial_sums[lid] = sum;
+    workgroupBarrier();
+
+    if lid == 0u {
+        var acc = 0u;
+        for (var i = 0u; i < PREFIX_SUM_THREADS; i++) {
+            let s = partial_sums[i];
+            partial_sums[i] = acc;
+            acc += s;
+        }
+    }
+    workgroupBarrier();
+
+    var acc = partial_sums[lid];
+    for (var c = begin; c < end; c++) {
+        cell_starts[c] = acc;
+        atomicStore(&cell_offsets[c], acc);
+        acc += atomicLoad(&cell_counts[c]);
+    }
+}
+
+@compute @workgroup_size(64)
+fn scatter_particles(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x < grid.num_particles {
+        let slot = atomicAdd(&cell_offsets[cell_index(positions[id.x])], 1u);
+        sorted_indices[slot] = id.x;
+    }
+}
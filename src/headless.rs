@@ -0,0 +1,405 @@
+use std::{
+    path::PathBuf,
+    sync::{mpsc, Arc},
+};
+
+use glam::Vec3;
+use image::RgbaImage;
+use pollster::FutureExt;
+use wgpu::{
+    Backends, Buffer, BufferDescriptor, BufferUsages, CommandEncoder, CommandEncoderDescriptor,
+    Device, Extent3d, ImageCopyBuffer, ImageDataLayout, Instance, InstanceDescriptor, Queue,
+    SurfaceConfiguration, Texture, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages, TextureView, TextureViewDescriptor,
+};
+
+use crate::{
+    attribute::ParticleAttribute,
+    camera::Camera,
+    camera_path::CameraPath,
+    config::{Colormap, ViewMode},
+    grid::GridState,
+    render::{RenderMode, RenderState},
+    scene::Scene,
+    simulation::{SimulationCommand, SimulationState},
+};
+
+const FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+const DEFAULT_SIZE: (u32, u32) = (1600, 900);
+
+pub struct HeadlessOptions {
+    pub output_dir: PathBuf,
+    pub frames: u32,
+    pub width: u32,
+    pub height: u32,
+    pub mode: RenderMode,
+    // * What the ray marcher shows
+    pub view: ViewMode,
+    // * Colouring of `ViewMode::Attribute`, the scene's if not given
+    pub attribute: Option<ParticleAttribute>,
+    pub colormap: Option<Colormap>,
+    pub color_range: Option<[f32; 2]>,
+    // * Camera position and view direction, the default camera if not given
+    pub camera: Option<(Vec3, Vec3)>,
+    // * Also streams the simulated frames to this recording
+    pub record: Option<PathBuf>,
+    // * Also exports the simulated frames as a VTK series, every `vtk_every` steps
+    pub vtk: Option<PathBuf>,
+    pub vtk_every: u32,
+}
+
+impl HeadlessOptions {
+    // * `--headless <dir> [--frames <n>] [--size <w>x<h>] [--mode raymarch|screenspace|mesh]
+    // * [--view shaded|normals|steps|depth|slice|miss|attribute]
+    // * [--attribute speed|density|pressure|vorticity|id] [--colormap viridis|turbo|coolwarm]
+    // * [--range <min>,<max>]
+    // * [--camera <px>,<py>,<pz>,<dx>,<dy>,<dz>] [--record <file>] [--vtk <dir> [--vtk-every <n>]]`,
+    // * None if `--headless` is not given
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut output_dir = None;
+        let mut frames = 1;
+        let (mut width, mut height) = DEFAULT_SIZE;
+        let mut mode = RenderMode::RayMarch;
+        let mut view = ViewMode::Shaded;
+        let mut attribute = None;
+        let mut colormap = None;
+        let mut color_range = None;
+        let mut camera = None;
+        let mut record = None;
+        let mut vtk = None;
+        let mut vtk_every = 1;
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {arg}"));
+            match arg.as_str() {
+                "--headless" => output_dir = Some(PathBuf::from(value()?)),
+                "--frames" => {
+                    frames = value()?
+                        .parse()
+                        .map_err(|e| format!("invalid --frames: {e}"))?;
+                    if frames == 0 {
+                        return Err("--frames must be at least 1".into());
+                    }
+                }
+                "--size" => {
+                    let size = value()?;
+                    let (w, h) = size
+                        .split_once('x')
+                        .ok_or(format!("invalid --size {size}, expected <w>x<h>"))?;
+                    width = w.parse().map_err(|e| format!("invalid --size: {e}"))?;
+                    height = h.parse().map_err(|e| format!("invalid --size: {e}"))?;
+                    if width == 0 || height == 0 {
+                        return Err(format!("invalid --size {size}, must not be empty"));
+                    }
+                }
+                "--mode" => {
+                    mode = match value()?.as_str() {
+                        "raymarch" => RenderMode::RayMarch,
+                        "screenspace" => RenderMode::ScreenSpace,
+                        "mesh" => RenderMode::Mesh,
+                        other => return Err(format!("unknown --mode {other}")),
+                    }
+                }
+                "--view" => {
+                    let name = value()?;
+                    view = ViewMode::from_name(&name).ok_or(format!("unknown --view {name}"))?;
+                }
+                "--attribute" => {
+                    let name = value()?;
+                    attribute = Some(
+                        ParticleAttribute::from_name(&name)
+                            .ok_or(format!("unknown --attribute {name}"))?,
+                    );
+                }
+                "--colormap" => {
+                    let name = value()?;
+                    colormap = Some(
+                        Colormap::from_name(&name).ok_or(format!("unknown --colormap {name}"))?,
+                    );
+                }
+                "--range" => {
+                    let range = value()?;
+                    let (min, max) = range
+                        .split_once(',')
+                        .ok_or(format!("invalid --range {range}, expected <min>,<max>"))?;
+                    color_range = Some([
+                        min.trim()
+                            .parse()
+                            .map_err(|e| format!("invalid --range: {e}"))?,
+                        max.trim()
+                            .parse()
+                            .map_err(|e| format!("invalid --range: {e}"))?,
+                    ]);
+                }
+                "--camera" => {
+                    let values = value()?
+                        .split(',')
+                        .map(|v| v.trim().parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| format!("invalid --camera: {e}"))?;
+                    let [px, py, pz, dx, dy, dz] = values[..] else {
+                        return Err("--camera expects 6 comma separated values".into());
+                    };
+                    camera = Some((Vec3::new(px, py, pz), Vec3::new(dx, dy, dz)));
+                }
+                "--record" => record = Some(PathBuf::from(value()?)),
+                "--vtk" => vtk = Some(PathBuf::from(value()?)),
+                "--vtk-every" => {
+                    vtk_every = value()?
+                        .parse()
+                        .map_err(|e| format!("invalid --vtk-every: {e}"))?;
+                    if vtk_every == 0 {
+                        return Err("--vtk-every must be at least 1".into());
+                    }
+                }
+                other => return Err(format!("unknown argument {other}")),
+            }
+        }
+
+        Ok(output_dir.map(|output_dir| Self {
+            output_dir,
+            frames,
+            width,
+            height,
+            mode,
+            view,
+            attribute,
+            colormap,
+            color_range,
+            camera,
+            record,
+            vtk,
+            vtk_every,
+        }))
+    }
+}
+
+// * Device and offscreen target without any window or surface, e.g. for CI on a software adapter
+pub struct HeadlessEnvironment {
+    pub device: Arc<Device>,
+    pub queue: Arc<Queue>,
+    // * Not backed by a surface, only describes the offscreen target for the pipelines
+    pub config: SurfaceConfiguration,
+    texture: Texture,
+    readback: Buffer,
+    padded_bytes_per_row: u32,
+}
+
+impl HeadlessEnvironment {
+    pub async fn new(width: u32, height: u32) -> Result<Self, String> {
+        // * CREATE INSTANCE
+        let instance = Instance::new(InstanceDescriptor {
+            backends: Backends::all(),
+            ..Default::default()
+        });
+
+        // * CREATE ADAPTER (fall back to a software adapter)
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptionsBase {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    force_fallback_adapter,
+                    compatible_surface: None,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.ok_or("no suitable graphics adapter found")?;
+
+        // * CREATE DEVICE & QUEUE
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .await
+            .map_err(|e| format!("failed to create a graphics device: {e}"))?;
+
+        // * CREATE OFFSCREEN TARGET
+        let config = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Offscreen Texture"),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let padded_bytes_per_row = (width * 4).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            config,
+            texture,
+            readback,
+            padded_bytes_per_row,
+        })
+    }
+
+    // * Renders into the offscreen texture and reads the result back
+    pub fn capture(&self, encode: impl FnOnce(&mut CommandEncoder, &TextureView)) -> RgbaImage {
+        let view = self.texture.create_view(&TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Offscreen Encoder"),
+            });
+        encode(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            self.texture.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        // * READ BACK, DROPPING THE ROW PADDING
+        let slice = self.readback.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .unwrap()
+            .expect("failed to map readback buffer");
+
+        let row_bytes = (self.config.width * 4) as usize;
+        let pixels = slice
+            .get_mapped_range()
+            .chunks_exact(self.padded_bytes_per_row as usize)
+            .flat_map(|row| row[..row_bytes].to_vec())
+            .collect();
+        self.readback.unmap();
+        RgbaImage::from_raw(self.config.width, self.config.height, pixels).unwrap()
+    }
+}
+
+// * Steps the fluid and writes one PNG per frame without opening a window, flying the camera
+// * along `camera_path` if given
+pub fn run(
+    options: &HeadlessOptions,
+    scene: &Scene,
+    camera_path: Option<&CameraPath>,
+) -> Result<(), String> {
+    let env = HeadlessEnvironment::new(options.width, options.height).block_on()?;
+    let mut config = scene.config();
+    config.set_view(options.view);
+    if let Some(attribute) = options.attribute {
+        config.set_attribute(attribute);
+    }
+    if let Some(colormap) = options.colormap {
+        config.set_colormap(colormap);
+    }
+    if options.color_range.is_some() {
+        config.set_color_range(options.color_range);
+    }
+
+    let (pos, dir) = options.camera.unwrap_or(scene.camera_pose());
+    let (mut camera, camera_bind_group_layout) =
+        Camera::create_camera(&env.device, &env.config, pos, dir);
+    let (mut simulation_state, simulation_bind_group_layout) =
+        SimulationState::create_simulation(scene, &env.device, &env.queue)?;
+    simulation_state.set_attribute(config.shown_attribute());
+    // * The first frame is coloured too
+    simulation_state.sync(&env.queue);
+    let (mut grid, grid_bind_group_layout) = GridState::create_grid(
+        &env.device,
+        &simulation_bind_group_layout,
+        simulation_state.num_particles(),
+    );
+    grid.rebuild(&env.device, &env.queue, &simulation_state, &config);
+    let mut render_state = RenderState::new(
+        &env.device,
+        &env.config,
+        camera_bind_group_layout,
+        simulation_bind_group_layout,
+        grid_bind_group_layout,
+        &config,
+    );
+    render_state.mode = options.mode;
+
+    std::fs::create_dir_all(&options.output_dir)
+        .map_err(|e| format!("failed to create {}: {e}", options.output_dir.display()))?;
+    if let Some(path) = &options.record {
+        simulation_state.send(SimulationCommand::Record(Some(path.clone())));
+    }
+    if let Some(dir) = &options.vtk {
+        simulation_state.send(SimulationCommand::ExportVtk {
+            dir: dir.clone(),
+            every: Some(options.vtk_every),
+        });
+    }
+    for frame in 0..options.frames {
+        if frame > 0 {
+            simulation_state.send(SimulationCommand::Step);
+            simulation_state.sync(&env.queue);
+            grid.rebuild(&env.device, &env.queue, &simulation_state, &config);
+            render_state.mesh.dirty = true;
+        }
+        if let Some(pose) = camera_path.and_then(|path| path.sample(simulation_state.time())) {
+            camera.set_pose(pose.pos, pose.dir, &env.queue);
+            camera.set_fov(pose.fov, &env.queue);
+        }
+        if render_state.mode == RenderMode::Mesh && render_state.mesh.dirty {
+            render_state.mesh.extract(
+                &env.device,
+                &env.queue,
+                &mut simulation_state,
+                &grid,
+                &config,
+            );
+        }
+        let image = env.capture(|encoder, view| {
+            render_state.encode(
+                encoder,
+                view,
+                &simulation_state.bind_group,
+                &camera.bind_group,
+                &grid.bind_group,
+                simulation_state.num_particles(),
+            )
+        });
+        let path = options.output_dir.join(format!("frame_{frame:05}.png"));
+        image
+            .save(&path)
+            .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+        println!("wrote {}", path.display());
+    }
+    if options.record.is_some() {
+        simulation_state.send(SimulationCommand::Record(None));
+    }
+    // * Waits for the last recorded and exported frames
+    simulation_state.sync(&env.queue);
+    Ok(())
+}
@@ -0,0 +1,512 @@
+use std::{
+    fs,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use glam::Vec3;
+use sph::sph::Particle;
+
+use crate::{backend::SimulationBackend, config::DEFAULT_DELTA_TIME, scene::ImportParams};
+
+// * Point files of other solvers, rendered without simulating. A path containing a run of `#`
+// * is a numbered sequence, e.g. `out/frame_####.ply` for frame_0000.ply, frame_0001.ply, ...
+// * and `out/frame_#.ply` for unpadded numbers.
+pub struct PointSequence {
+    files: Vec<PathBuf>,
+    // * File units to simulation units
+    scale: f32,
+    frame: usize,
+    positions: Vec<Vec3>,
+}
+
+impl PointSequence {
+    pub fn open(params: &ImportParams) -> Result<Self, String> {
+        let files = frame_files(&params.path)?;
+        let positions = read_points(&files[0], params.scale)?;
+        if positions.is_empty() {
+            return Err(format!("{} has no points", files[0].display()));
+        }
+        Ok(Self {
+            files,
+            scale: params.scale,
+            frame: 0,
+            positions,
+        })
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    // * Frames with a different particle count than the first one are skipped
+    fn show(&mut self, frame: usize) {
+        let frame = frame.min(self.files.len() - 1);
+        if frame == self.frame {
+            return;
+        }
+        match read_points(&self.files[frame], self.scale) {
+            Ok(positions) if positions.len() != self.positions.len() => eprintln!(
+                "{} has {} points instead of {}, skipped",
+                self.files[frame].display(),
+                positions.len(),
+                self.positions.len()
+            ),
+            Ok(positions) => self.positions = positions,
+            Err(e) => eprintln!("{e}"),
+        }
+        self.frame = frame;
+    }
+}
+
+impl SimulationBackend for PointSequence {
+    // * One file per step
+    fn step(&mut self, _dt: f32) {
+        self.show(self.frame + 1);
+    }
+
+    fn positions(&self) -> Vec<Vec3> {
+        self.positions.clone()
+    }
+
+    fn num_particles(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn reset(&mut self, _particles: Vec<Particle>) {
+        self.show(0);
+    }
+
+    fn seek(&mut self, to: SeekFrom) {
+        let frame = match to {
+            SeekFrom::Start(frame) => frame as i64,
+            SeekFrom::Current(offset) => self.frame as i64 + offset,
+            SeekFrom::End(offset) => self.files.len() as i64 - 1 + offset,
+        };
+        self.show(frame.max(0) as usize);
+        println!("frame {}/{}", self.frame, self.files.len() - 1);
+    }
+
+    // * The files do not store a time step, each counts as one solver step
+    fn time(&self) -> Option<f64> {
+        Some(self.frame as f64 * DEFAULT_DELTA_TIME as f64)
+    }
+}
+
+// * The files of a sequence in frame order, or just `path` if it has no `#`
+fn frame_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(format!("invalid import path {}", path.display()))?;
+    let Some(start) = name.find('#') else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+    let end = start
+        + name[start..]
+            .find(|c| c != '#')
+            .unwrap_or(name.len() - start);
+    let (prefix, suffix) = (&name[..start], &name[end..]);
+    let width = end - start;
+
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("failed to read {}: {e}", dir.display()))?;
+    let mut frames: Vec<(u64, PathBuf)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let file_name = entry.file_name();
+            let number = file_name
+                .to_str()?
+                .strip_prefix(prefix)?
+                .strip_suffix(suffix)?;
+            // * A padded pattern only matches numbers of exactly that width
+            let matches = !number.is_empty()
+                && number.bytes().all(|b| b.is_ascii_digit())
+                && (width == 1 || number.len() == width);
+            if !matches {
+                return None;
+            }
+            Some((number.parse().ok()?, entry.path()))
+        })
+        .collect();
+    if frames.is_empty() {
+        return Err(format!("no files match {}", path.display()));
+    }
+    frames.sort();
+    Ok(frames.into_iter().map(|(_, path)| path).collect())
+}
+
+// * Positions from a .ply or .csv file, multiplied by `scale`
+pub fn read_points(path: &Path, scale: f32) -> Result<Vec<Vec3>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    let points = match extension.as_deref() {
+        Some("ply") => parse_ply(&bytes),
+        Some("csv") => parse_csv(&String::from_utf8_lossy(&bytes)),
+        _ => Err("unknown point format, expected .ply or .csv".into()),
+    }
+    .map_err(|e| format!("invalid point file {}: {e}", path.display()))?;
+    Ok(points.into_iter().map(|p| p * scale).collect())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+// * Only the x, y and z properties of the vertex element are read, which has to come first
+fn parse_ply(bytes: &[u8]) -> Result<Vec<Vec3>, String> {
+    const END_HEADER: &[u8] = b"end_header";
+    let header_end = bytes
+        .windows(END_HEADER.len())
+        .position(|w| w == END_HEADER)
+        .ok_or("missing end_header")?;
+    let mut body = header_end + END_HEADER.len();
+    // * The header ends with a single line break, \r\n for some writers
+    if bytes.get(body) == Some(&b'\r') {
+        body += 1;
+    }
+    if bytes.get(body) == Some(&b'\n') {
+        body += 1;
+    }
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+    let mut lines = header.lines().map(str::trim);
+    if lines.next() != Some("ply") {
+        return Err("not a PLY file".into());
+    }
+
+    let mut format = None;
+    let mut vertices = None;
+    // * (type, name) of the vertex properties
+    let mut properties: Vec<(String, String)> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["format", kind, _] => {
+                format = Some(match kind {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::LittleEndian,
+                    "binary_big_endian" => PlyFormat::BigEndian,
+                    other => return Err(format!("unknown format {other}")),
+                })
+            }
+            ["element", "vertex", count] if vertices.is_none() => {
+                vertices = Some(
+                    count
+                        .parse::<usize>()
+                        .map_err(|e| format!("invalid vertex count: {e}"))?,
+                )
+            }
+            ["element", ..] if vertices.is_none() => {
+                return Err("the vertex element has to come first".into())
+            }
+            ["element", ..] => break,
+            ["property", "list", ..] => return Err("list properties on vertices".into()),
+            ["property", ty, name] => properties.push((ty.into(), name.into())),
+            _ => {}
+        }
+    }
+    let format = format.ok_or("missing format")?;
+    let vertices = vertices.ok_or("missing vertex element")?;
+    let column = |axis: &str| {
+        properties
+            .iter()
+            .position(|(_, name)| name == axis)
+            .ok_or(format!("missing property {axis}"))
+    };
+    let columns = [column("x")?, column("y")?, column("z")?];
+
+    if format == PlyFormat::Ascii {
+        let body = String::from_utf8_lossy(&bytes[body..]);
+        let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+        return (0..vertices)
+            .map(|i| {
+                let line = lines.next().ok_or(format!("missing vertex {i}"))?;
+                let values: Vec<&str> = line.split_whitespace().collect();
+                let value = |c: usize| -> Result<f32, String> {
+                    values
+                        .get(c)
+                        .ok_or(format!("vertex {i} is too short"))?
+                        .parse()
+                        .map_err(|e| format!("vertex {i}: {e}"))
+                };
+                Ok(Vec3::new(
+                    value(columns[0])?,
+                    value(columns[1])?,
+                    value(columns[2])?,
+                ))
+            })
+            .collect();
+    }
+
+    let sizes = properties
+        .iter()
+        .map(|(ty, _)| ply_type_size(ty).ok_or(format!("unknown property type {ty}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    let stride: usize = sizes.iter().sum();
+    let offset = |c: usize| sizes[..c].iter().sum::<usize>();
+    let data = &bytes[body..];
+    let size = vertices.checked_mul(stride).ok_or(format!(
+        "{vertices} vertices of {stride} bytes are too many"
+    ))?;
+    if data.len() < size {
+        return Err(format!("expected {vertices} vertices of {stride} bytes"));
+    }
+    let value = |vertex: &[u8], c: usize| {
+        let (ty, _) = &properties[c];
+        read_ply_value(&vertex[offset(c)..offset(c) + sizes[c]], ty, format)
+    };
+    Ok(data
+        .chunks_exact(stride)
+        .take(vertices)
+        .map(|vertex| {
+            Vec3::new(
+                value(vertex, columns[0]),
+                value(vertex, columns[1]),
+                value(vertex, columns[2]),
+            )
+        })
+        .collect())
+}
+
+fn ply_type_size(ty: &str) -> Option<usize> {
+    match ty {
+        "char" | "uchar" | "int8" | "uint8" => Some(1),
+        "short" | "ushort" | "int16" | "uint16" => Some(2),
+        "int" | "uint" | "float" | "int32" | "uint32" | "float32" => Some(4),
+        "double" | "float64" => Some(8),
+        _ => None,
+    }
+}
+
+fn read_ply_value(bytes: &[u8], ty: &str, format: PlyFormat) -> f32 {
+    macro_rules! read {
+        ($t:ty) => {{
+            let bytes = bytes.try_into().unwrap();
+            match format {
+                PlyFormat::BigEndian => <$t>::from_be_bytes(bytes),
+                _ => <$t>::from_le_bytes(bytes),
+            }
+        }};
+    }
+    match ty {
+        "char" | "int8" => read!(i8) as f32,
+        "uchar" | "uint8" => read!(u8) as f32,
+        "short" | "int16" => read!(i16) as f32,
+        "ushort" | "uint16" => read!(u16) as f32,
+        "int" | "int32" => read!(i32) as f32,
+        "uint" | "uint32" => read!(u32) as f32,
+        "float" | "float32" => read!(f32),
+        _ => read!(f64) as f32,
+    }
+}
+
+// * Comma separated, with a header naming the x, y and z columns (also ParaView's Points:0..2)
+// * or without one, then the first three columns are the position
+fn parse_csv(source: &str) -> Result<Vec<Vec3>, String> {
+    let mut lines = source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let fields = |line: &str| -> Vec<String> {
+        line.split(',')
+            .map(|f| f.trim().trim_matches('"').to_lowercase())
+            .collect()
+    };
+
+    let mut columns = [0, 1, 2];
+    let mut points = vec![];
+    if let Some((_, first)) = lines.next() {
+        let header = fields(first);
+        if header.iter().all(|f| f.parse::<f32>().is_ok()) {
+            points.push(csv_point(&header, columns, 1)?);
+        } else {
+            for (axis, column) in columns.iter_mut().enumerate() {
+                let names = [["x", "points:0"], ["y", "points:1"], ["z", "points:2"]][axis];
+                *column = header
+                    .iter()
+                    .position(|f| names.contains(&f.as_str()))
+                    .ok_or(format!("no {} column in the header", names[0]))?;
+            }
+        }
+    }
+    for (i, line) in lines {
+        points.push(csv_point(&fields(line), columns, i + 1)?);
+    }
+    Ok(points)
+}
+
+fn csv_point(fields: &[String], columns: [usize; 3], line: usize) -> Result<Vec3, String> {
+    let value = |c: usize| -> Result<f32, String> {
+        fields
+            .get(c)
+            .ok_or(format!("line {line} is too short"))?
+            .parse()
+            .map_err(|e| format!("line {line}: {e}"))
+    };
+    Ok(Vec3::new(
+        value(columns[0])?,
+        value(columns[1])?,
+        value(columns[2])?,
+    ))
+}
+
+// * Removes `--import <path>` and `--import-scale <scale>` from the arguments
+pub fn take_import_arg(args: &mut Vec<String>) -> Result<Option<ImportParams>, String> {
+    let mut take = |flag: &str| -> Result<Option<String>, String> {
+        let Some(i) = args.iter().position(|arg| arg == flag) else {
+            return Ok(None);
+        };
+        if i + 1 >= args.len() {
+            return Err(format!("missing value for {flag}"));
+        }
+        let value = args.remove(i + 1);
+        args.remove(i);
+        Ok(Some(value))
+    };
+    let path = take("--import")?;
+    let scale = take("--import-scale")?
+        .map(|s| {
+            s.parse()
+                .map_err(|e| format!("invalid --import-scale: {e}"))
+        })
+        .transpose()?;
+    match (path, scale) {
+        (Some(path), scale) => Ok(Some(ImportParams {
+            path: PathBuf::from(path),
+            scale: scale.unwrap_or(1.),
+        })),
+        (None, Some(_)) => Err("--import-scale needs --import".into()),
+        (None, None) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ply_header(format: &str, vertices: usize, properties: &str) -> Vec<u8> {
+        format!("ply\nformat {format} 1.0\nelement vertex {vertices}\n{properties}end_header\n")
+            .into_bytes()
+    }
+
+    const XYZ: &str = "property float x\nproperty float y\nproperty float z\n";
+
+    #[test]
+    fn ascii_ply() {
+        let mut bytes = ply_header("ascii", 2, XYZ);
+        bytes.extend(b"1 2 3\n4.5 -5 6\n");
+        let points = parse_ply(&bytes).unwrap();
+        assert_eq!(points, [Vec3::new(1., 2., 3.), Vec3::new(4.5, -5., 6.)]);
+    }
+
+    #[test]
+    fn ascii_ply_with_crlf_header() {
+        let header = "ply\r\nformat ascii 1.0\r\nelement vertex 1\r\nproperty float x\r\n\
+                      property float y\r\nproperty float z\r\nend_header\r\n1 2 3\r\n";
+        assert_eq!(
+            parse_ply(header.as_bytes()).unwrap(),
+            [Vec3::new(1., 2., 3.)]
+        );
+    }
+
+    #[test]
+    fn binary_little_endian_ply_skips_other_properties() {
+        let properties = "property uchar red\nproperty float z\nproperty float x\n\
+                          property double y\n";
+        let mut bytes = ply_header("binary_little_endian", 2, properties);
+        for (red, x, y, z) in [(7u8, 1f32, 2f64, 3f32), (8, 4., 5., 6.)] {
+            bytes.push(red);
+            bytes.extend(z.to_le_bytes());
+            bytes.extend(x.to_le_bytes());
+            bytes.extend(y.to_le_bytes());
+        }
+        let points = parse_ply(&bytes).unwrap();
+        assert_eq!(points, [Vec3::new(1., 2., 3.), Vec3::new(4., 5., 6.)]);
+    }
+
+    #[test]
+    fn binary_big_endian_ply() {
+        let mut bytes = ply_header("binary_big_endian", 1, XYZ);
+        for v in [1f32, -2., 3.] {
+            bytes.extend(v.to_be_bytes());
+        }
+        assert_eq!(parse_ply(&bytes).unwrap(), [Vec3::new(1., -2., 3.)]);
+    }
+
+    #[test]
+    fn ply_errors() {
+        let missing_z = ply_header("ascii", 1, "property float x\nproperty float y\n");
+        assert!(parse_ply(&missing_z)
+            .unwrap_err()
+            .contains("missing property z"));
+
+        let mut truncated = ply_header("binary_little_endian", 2, XYZ);
+        truncated.extend([0; 12]);
+        assert!(parse_ply(&truncated).is_err());
+
+        let huge = ply_header("binary_little_endian", usize::MAX, XYZ);
+        assert!(parse_ply(&huge).unwrap_err().contains("too many"));
+
+        assert!(parse_ply(b"not a ply file").is_err());
+    }
+
+    #[test]
+    fn csv_with_header() {
+        let points = parse_csv("id,\"Points:2\",Points:0,Points:1\n0,3,1,2\n1,6,4,5\n").unwrap();
+        assert_eq!(points, [Vec3::new(1., 2., 3.), Vec3::new(4., 5., 6.)]);
+    }
+
+    #[test]
+    fn csv_without_header() {
+        let points = parse_csv("1, 2, 3, 99\r\n\r\n4, 5, 6, 99\r\n").unwrap();
+        assert_eq!(points, [Vec3::new(1., 2., 3.), Vec3::new(4., 5., 6.)]);
+    }
+
+    #[test]
+    fn csv_errors() {
+        assert!(parse_csv("x,y,w\n1,2,3\n")
+            .unwrap_err()
+            .contains("no z column"));
+        assert!(parse_csv("x,y,z\n1,2\n").unwrap_err().contains("too short"));
+        assert!(parse_csv("x,y,z\n1,2,a\n").is_err());
+        assert_eq!(parse_csv("x,y,z\n").unwrap(), []);
+    }
+
+    #[test]
+    fn sequence_patterns() {
+        let dir = std::env::temp_dir().join(format!("import_sequence_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "f_0002.csv",
+            "f_0010.csv",
+            "f_1.csv",
+            "f_0003.txt",
+            "g_0001.csv",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let names = |pattern: &str| -> Vec<String> {
+            frame_files(&dir.join(pattern))
+                .unwrap()
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        // * Padded patterns only match numbers of their width, unpadded ones any number
+        assert_eq!(names("f_####.csv"), ["f_0002.csv", "f_0010.csv"]);
+        assert_eq!(names("f_#.csv"), ["f_1.csv", "f_0002.csv", "f_0010.csv"]);
+        assert_eq!(names("f_1.csv"), ["f_1.csv"]);
+        assert!(frame_files(&dir.join("h_#.csv")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,34 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
 use winit::event::{ElementState, VirtualKeyCode, WindowEvent};
 
-use crate::state::State;
+use crate::{
+    camera::CameraMode,
+    camera_path::CameraPath,
+    marching_cubes::{Mesh, MESH_CELL_SIZE},
+    mesh::MeshBackend,
+    simulation::SimulationCommand,
+    state::State,
+};
+
+const MESH_EXPORT_PATH: &str = "mesh.obj";
+const CHECKPOINT_PATH: &str = "checkpoint.bin";
+const RECORDING_PATH: &str = "recording.rec";
+const VTK_EXPORT_DIR: &str = "vtk";
+const CAMERA_PATH_PATH: &str = "camera_path.toml";
+// * Frames skipped by page up / page down during playback
+const SEEK_FRAMES: i64 = 50;
+// * View space distance the slice view moves per key press
+const SLICE_STEP: f32 = 10.;
 
 pub fn input(state: &mut State, event: &WindowEvent) -> bool {
     // * HANDLE CAMERA INPUT FIRST
-    if state.camera.controller.handle_key_input(event) {
+    if state.camera.controller.handle_key_input(event)
+        || state.camera.controller.handle_mouse_input(event)
+    {
         true
     } else {
         match event {
@@ -14,7 +38,47 @@ pub fn input(state: &mut State, event: &WindowEvent) -> bool {
                     && input.state == ElementState::Released
                     && state.paused =>
             {
-                state.update_simulation();
+                state.simulation_state.send(SimulationCommand::Step);
+                true
+            }
+            // * PLAYBACK TIMELINE: STEP BACK, JUMP, START / END
+            WindowEvent::KeyboardInput { input, .. }
+                if matches!(
+                    input.virtual_keycode,
+                    Some(
+                        VirtualKeyCode::Back
+                            | VirtualKeyCode::PageUp
+                            | VirtualKeyCode::PageDown
+                            | VirtualKeyCode::Home
+                            | VirtualKeyCode::End
+                    )
+                ) && input.state == ElementState::Released
+                    && state.scene.is_playback() =>
+            {
+                let to = match input.virtual_keycode {
+                    Some(VirtualKeyCode::Back) => SeekFrom::Current(-1),
+                    Some(VirtualKeyCode::PageUp) => SeekFrom::Current(-SEEK_FRAMES),
+                    Some(VirtualKeyCode::PageDown) => SeekFrom::Current(SEEK_FRAMES),
+                    Some(VirtualKeyCode::Home) => SeekFrom::Start(0),
+                    _ => SeekFrom::End(0),
+                };
+                state.simulation_state.send(SimulationCommand::Seek(to));
+                true
+            }
+            // * SLOW DOWN / SPEED UP SIMULATED TIME
+            WindowEvent::KeyboardInput { input, .. }
+                if matches!(
+                    input.virtual_keycode,
+                    Some(VirtualKeyCode::LBracket | VirtualKeyCode::RBracket)
+                ) && input.state == ElementState::Released =>
+            {
+                let factor = match input.virtual_keycode {
+                    Some(VirtualKeyCode::LBracket) => 0.5,
+                    _ => 2.,
+                };
+                let time_scale = state.timestep.time_scale() * factor;
+                state.timestep.set_time_scale(time_scale);
+                println!("time scale: {}", state.timestep.time_scale());
                 true
             }
             // * TOGGLE PAUSE
@@ -22,7 +86,300 @@ pub fn input(state: &mut State, event: &WindowEvent) -> bool {
                 if input.virtual_keycode == Some(VirtualKeyCode::Space)
                     && input.state == ElementState::Released =>
             {
-                state.paused = !state.paused;
+                state.set_paused(!state.paused);
+                true
+            }
+            // * RESET TO THE INITIAL STATE OF THE SCENE
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::R)
+                    && input.state == ElementState::Released =>
+            {
+                state.reset();
+                true
+            }
+            // * RELOAD THE SCENE FILE AND RESTART WITH IT
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::L)
+                    && input.state == ElementState::Released =>
+            {
+                match state.reload_scene() {
+                    Ok(()) => println!("scene reloaded"),
+                    Err(e) => eprintln!("{e}"),
+                }
+                true
+            }
+            // * SAVE / LOAD A CHECKPOINT
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::F5)
+                    && input.state == ElementState::Released =>
+            {
+                match state.save_checkpoint(Path::new(CHECKPOINT_PATH)) {
+                    Ok(()) => println!("saved checkpoint to {CHECKPOINT_PATH}"),
+                    Err(e) => eprintln!("{e}"),
+                }
+                true
+            }
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::F9)
+                    && input.state == ElementState::Released =>
+            {
+                match state.load_checkpoint(Path::new(CHECKPOINT_PATH)) {
+                    Ok(()) => println!("loaded checkpoint from {CHECKPOINT_PATH}"),
+                    Err(e) => eprintln!("{e}"),
+                }
+                true
+            }
+            // * EXPORT THE CURRENT FRAME FOR PARAVIEW
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::V)
+                    && input.state == ElementState::Released =>
+            {
+                state.simulation_state.send(SimulationCommand::ExportVtk {
+                    dir: PathBuf::from(VTK_EXPORT_DIR),
+                    every: None,
+                });
+                true
+            }
+            // * START / STOP RECORDING
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::F6)
+                    && input.state == ElementState::Released =>
+            {
+                if state.recording {
+                    state.record(None);
+                } else {
+                    state.record(Some(PathBuf::from(RECORDING_PATH)));
+                    println!("recording to {RECORDING_PATH}");
+                }
+                true
+            }
+            // * TUNE THE RENDER CONFIG: 1/2 ALPHA, 3/4 RADIUS, 5/6 STEPS, 7/8 MAX DISTANCE
+            WindowEvent::KeyboardInput { input, .. }
+                if matches!(
+                    input.virtual_keycode,
+                    Some(
+                        VirtualKeyCode::Key1
+                            | VirtualKeyCode::Key2
+                            | VirtualKeyCode::Key3
+                            | VirtualKeyCode::Key4
+                            | VirtualKeyCode::Key5
+                            | VirtualKeyCode::Key6
+                            | VirtualKeyCode::Key7
+                            | VirtualKeyCode::Key8
+                    )
+                ) && input.state == ElementState::Released =>
+            {
+                let mut config = state.config.clone();
+                match input.virtual_keycode {
+                    Some(VirtualKeyCode::Key1) => config.set_alpha(config.alpha() / 1.25),
+                    Some(VirtualKeyCode::Key2) => config.set_alpha(config.alpha() * 1.25),
+                    Some(VirtualKeyCode::Key3) => config.set_radius(config.radius() - 0.5),
+                    Some(VirtualKeyCode::Key4) => config.set_radius(config.radius() + 0.5),
+                    Some(VirtualKeyCode::Key5) => config.set_max_steps(config.max_steps() / 2),
+                    Some(VirtualKeyCode::Key6) => config.set_max_steps(config.max_steps() * 2),
+                    Some(VirtualKeyCode::Key7) => {
+                        config.set_max_distance(config.max_distance() / 2.)
+                    }
+                    _ => config.set_max_distance(config.max_distance() * 2.),
+                }
+                state.set_config(config);
+                println!("{:?}", state.config);
+                true
+            }
+            // * SHOW / HIDE THE OVERLAY
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::F1)
+                    && input.state == ElementState::Released =>
+            {
+                state.overlay.visible = !state.overlay.visible;
+                true
+            }
+            // * CYCLE RAY MARCHER VIEW, MOVE THE SLICE WITH 9/0
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::C)
+                    && input.state == ElementState::Released =>
+            {
+                let mut config = state.config.clone();
+                config.set_view(config.view().next());
+                state.set_config(config);
+                println!("view: {}", state.config.view().name());
+                true
+            }
+            WindowEvent::KeyboardInput { input, .. }
+                if matches!(
+                    input.virtual_keycode,
+                    Some(VirtualKeyCode::Key9 | VirtualKeyCode::Key0)
+                ) && input.state == ElementState::Released =>
+            {
+                let step = match input.virtual_keycode {
+                    Some(VirtualKeyCode::Key9) => -SLICE_STEP,
+                    _ => SLICE_STEP,
+                };
+                let mut config = state.config.clone();
+                config.set_slice_depth(config.slice_depth() + step);
+                state.set_config(config);
+                println!("slice depth: {}", state.config.slice_depth());
+                true
+            }
+            // * CYCLE THE COLOURED ATTRIBUTE WITH K, THE COLORMAP WITH J
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::K)
+                    && input.state == ElementState::Released =>
+            {
+                let mut config = state.config.clone();
+                config.set_attribute(config.attribute().next());
+                state.set_config(config);
+                println!("attribute: {}", state.config.attribute().name());
+                true
+            }
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::J)
+                    && input.state == ElementState::Released =>
+            {
+                let mut config = state.config.clone();
+                config.set_colormap(config.colormap().next());
+                state.set_config(config);
+                println!("colormap: {}", state.config.colormap().name());
+                true
+            }
+            // * CYCLE HOW BOUNDARY PARTICLES ARE DRAWN
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::B)
+                    && input.state == ElementState::Released =>
+            {
+                let mut config = state.config.clone();
+                config.set_boundary(config.boundary().next());
+                state.set_config(config);
+                println!("boundary: {}", state.config.boundary().name());
+                true
+            }
+            // * TOGGLE GRID ACCELERATION
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::G)
+                    && input.state == ElementState::Released =>
+            {
+                let enabled = !state.grid.enabled();
+                state.grid.set_enabled(enabled, &state.env.queue);
+                println!("grid acceleration: {}", enabled);
+                true
+            }
+            // * CYCLE RENDER MODE
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::M)
+                    && input.state == ElementState::Released =>
+            {
+                state.render_state.mode = state.render_state.mode.next();
+                println!("render mode: {:?}", state.render_state.mode);
+                true
+            }
+            // * TOGGLE MESH EXTRACTION BACKEND
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::N)
+                    && input.state == ElementState::Released =>
+            {
+                let mesh = &mut state.render_state.mesh;
+                mesh.backend = match mesh.backend {
+                    MeshBackend::Gpu => MeshBackend::Cpu,
+                    MeshBackend::Cpu => MeshBackend::Gpu,
+                };
+                mesh.dirty = true;
+                println!("mesh backend: {:?}", mesh.backend);
+                true
+            }
+            // * EXPORT SURFACE MESH
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::X)
+                    && input.state == ElementState::Released =>
+            {
+                let positions = state
+                    .simulation_state
+                    .surface_positions(state.config.surface_includes_boundary());
+                let mesh = Mesh::extract(&positions, &state.config, MESH_CELL_SIZE);
+                match mesh.write_obj(MESH_EXPORT_PATH) {
+                    Ok(()) => println!(
+                        "exported {} triangles to {}",
+                        mesh.indices.len() / 3,
+                        MESH_EXPORT_PATH
+                    ),
+                    Err(e) => eprintln!("failed to export mesh: {e}"),
+                }
+                true
+            }
+            // * TOGGLE BETWEEN FLY AND ORBIT CAMERA
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::O)
+                    && input.state == ElementState::Released =>
+            {
+                let mode = match state.camera.controller.mode {
+                    CameraMode::Fly => CameraMode::Orbit,
+                    CameraMode::Orbit => CameraMode::Fly,
+                };
+                state.set_camera_mode(mode);
+                println!("camera: {}", mode.name());
+                true
+            }
+            // * KEYFRAME THE CAMERA AT THE CURRENT SIMULATION TIME
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::P)
+                    && input.state == ElementState::Released =>
+            {
+                state.add_keyframe();
+                true
+            }
+            // * PLAY THE CAMERA PATH
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::T)
+                    && input.state == ElementState::Released =>
+            {
+                state.playing_path = !state.playing_path;
+                println!(
+                    "camera path: {}",
+                    if state.playing_path {
+                        "playing"
+                    } else {
+                        "stopped"
+                    }
+                );
+                true
+            }
+            // * CLEAR THE CAMERA PATH
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::Z)
+                    && input.state == ElementState::Released =>
+            {
+                state.camera_path.keyframes.clear();
+                state.playing_path = false;
+                println!("cleared camera path");
+                true
+            }
+            // * SAVE / LOAD THE CAMERA PATH
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::F3)
+                    && input.state == ElementState::Released =>
+            {
+                match state.camera_path.save(Path::new(CAMERA_PATH_PATH)) {
+                    Ok(()) => println!(
+                        "saved {} keyframes to {CAMERA_PATH_PATH}",
+                        state.camera_path.keyframes.len()
+                    ),
+                    Err(e) => eprintln!("{e}"),
+                }
+                true
+            }
+            WindowEvent::KeyboardInput { input, .. }
+                if input.virtual_keycode == Some(VirtualKeyCode::F4)
+                    && input.state == ElementState::Released =>
+            {
+                match CameraPath::load(Path::new(CAMERA_PATH_PATH)) {
+                    Ok(camera_path) => {
+                        println!(
+                            "loaded {} keyframes from {CAMERA_PATH_PATH}",
+                            camera_path.keyframes.len()
+                        );
+                        state.camera_path = camera_path;
+                    }
+                    Err(e) => eprintln!("{e}"),
+                }
                 true
             }
             // * TOGGLE CURSOR GRAB
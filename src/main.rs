@@ -1,81 +1,167 @@
+pub(crate) mod attribute;
+pub(crate) mod backend;
 pub(crate) mod camera;
+pub(crate) mod camera_path;
+pub(crate) mod checkpoint;
 pub(crate) mod config;
 pub(crate) mod env;
+pub(crate) mod gpu_sph;
+pub(crate) mod grid;
+pub(crate) mod headless;
+pub(crate) mod import;
 pub(crate) mod input;
+pub(crate) mod marching_cubes;
+pub(crate) mod mesh;
+pub(crate) mod pbf;
+pub(crate) mod recording;
 pub(crate) mod render;
+pub(crate) mod scene;
+pub(crate) mod screen_space;
 pub(crate) mod simulation;
 pub(crate) mod state;
+pub(crate) mod timestep;
+pub(crate) mod ui;
 pub(crate) mod vertex;
+pub(crate) mod vtk;
 
 use camera::Camera;
-use config::{Config, DEFAULT_DELTA_TIME};
+use camera_path::CameraPath;
+use config::DEFAULT_DELTA_TIME;
 use env::Environment;
-use glam::{vec3a, Vec3A};
+use grid::GridState;
+use headless::HeadlessOptions;
 use pollster::FutureExt;
 use render::RenderState;
+use scene::Scene;
 use simulation::SimulationState;
-use sph::{fluid::Fluid, sph::Particle};
 use state::State;
+use timestep::FixedTimestep;
+use ui::Overlay;
 use winit::event_loop::EventLoop;
 
 fn main() {
-    let config = Config::default();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+
+    // * LOAD SCENE (`--scene <file.toml>`, the built-in cube in a box otherwise)
+    let scene_path = scene::take_scene_arg(&mut args).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    let scene = match &scene_path {
+        Some(path) => Scene::load(path),
+        None => Ok(Scene::default()),
+    };
+    let scene = scene.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    // * PLAY A RECORDING BACK INSTEAD OF SOLVING (`--replay <file>`)
+    let scene = match recording::take_replay_arg(&mut args) {
+        Ok(Some(path)) => {
+            let mut scene = scene;
+            scene.set_replay(path).map(|()| scene)
+        }
+        Ok(None) => Ok(scene),
+        Err(e) => Err(e),
+    };
+    // * RENDER POINT FILES OF ANOTHER SOLVER (`--import <file> [--import-scale <s>]`)
+    let scene = scene.and_then(|mut scene| match import::take_import_arg(&mut args)? {
+        Some(import) => scene.set_import(import).map(|()| scene),
+        None => Ok(scene),
+    });
+    let scene = scene.unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+    let config = scene.config();
+
+    // * COMPARE THE GPU SOLVER AGAINST THE CPU (`--cross-check <steps>`)
+    match gpu_sph::take_cross_check_arg(&mut args) {
+        Ok(Some(steps)) => match gpu_sph::cross_check(&scene, steps) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+
+    // * FLY THE CAMERA ALONG KEYFRAMES (`--camera-path <file.toml>`)
+    let camera_path = camera_path::take_camera_path_arg(&mut args)
+        .and_then(|path| path.map(|path| CameraPath::load(&path)).transpose())
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+
+    // * RENDER TO PNG FILES WITHOUT A WINDOW
+    match HeadlessOptions::from_args(args.into_iter()) {
+        Ok(Some(options)) => match headless::run(&options, &scene, camera_path.as_ref()) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        },
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
 
     let event_loop = EventLoop::new();
 
     let env = Environment::new(&event_loop).block_on();
 
-    let (camera, camera_bind_group_layout) = Camera::create_camera(&env.device, &env.config);
+    let (pos, dir) = scene.camera_pose();
+    let (camera, camera_bind_group_layout) =
+        Camera::create_camera(&env.device, &env.config, pos, dir);
 
-    // let particles = vec![
-    //     Particle::new(vec3a(50., 50., 50.), 1., true),
-    //     Particle::new(vec3a(75., 75., 75.), 1., true),
-    //     Particle::new(vec3a(80., 80., 80.), 1., true),
-    //     Particle::new(vec3a(80., 80., 80.), 1., true),
-    //     Particle::new(vec3a(80., 80., 80.), 1., true),
-    //     Particle::new(vec3a(80., 80., 80.), 1., true),
-    //     Particle::new(vec3a(80., 80., 80.), 1., true),
-    // ];
-    let mut particles = cube_in_box(2);
-    // particles.push(Particle::new(vec3a(0., 0., 0.), 1., true));
-    let fluid = Fluid::new(particles, 1.0, 1., 1.0e-3, 5000.0, -9.81 * Vec3A::Z);
     let (simulation_state, simulation_bind_group_layout) =
-        SimulationState::create_simulation(fluid, &env.device);
+        SimulationState::create_simulation(&scene, &env.device, &env.queue).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        });
+    let (mut grid, grid_bind_group_layout) = GridState::create_grid(
+        &env.device,
+        &simulation_bind_group_layout,
+        simulation_state.num_particles(),
+    );
+    grid.rebuild(&env.device, &env.queue, &simulation_state, &config);
 
     let render_state = RenderState::new(
-        &env,
+        &env.device,
+        &env.config,
         camera_bind_group_layout,
         simulation_bind_group_layout,
+        grid_bind_group_layout,
         &config,
     );
+    let overlay = Overlay::new(&event_loop, &env.window, &env.device, env.config.format);
 
     let state = State {
         simulation_state,
+        grid,
         env,
         render_state,
         camera,
         config,
         delta_time: DEFAULT_DELTA_TIME,
         paused: true,
+        scene,
+        scene_path,
+        timestep: FixedTimestep::default(),
+        recording: false,
+        playing_path: camera_path.is_some(),
+        camera_path: camera_path.unwrap_or_default(),
+        overlay,
     };
 
     state.run(event_loop);
 }
-fn cube_in_box(extend: i32) -> Vec<Particle> {
-    let mut particles = vec![];
-    for x in -extend..=extend {
-        for y in -extend..=extend {
-            for z in -extend..=extend {
-                if z < 0 || (x.abs() < extend && y.abs() < extend) {
-                    let movable = x.abs() < extend && y.abs() < extend && z > -extend;
-                    particles.push(Particle::new(
-                        vec3a(x as f32, z as f32, y as f32),
-                        1.,
-                        movable,
-                    ));
-                }
-            }
-        }
-    }
-    particles
-}
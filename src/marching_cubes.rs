@@ -0,0 +1,350 @@
+use std::{
+    collections::HashMap, fs::File, io, io::BufWriter, io::Write, path::Path, sync::OnceLock,
+};
+
+use glam::{UVec3, Vec3};
+
+use crate::{config::Config, simulation::particle_bounds};
+
+// * UPPER BOUND OF GRID NODES PER AXIS, THE CELL SIZE GROWS INSTEAD
+pub const MAX_MESH_DIM: u32 = 128;
+// * DEFAULT EDGE LENGTH OF A MARCHING CUBES CELL IN WORLD UNITS
+pub const MESH_CELL_SIZE: f32 = 2.5;
+// * ENTRIES PER CASE IN THE TRIANGLE TABLE, -1 TERMINATED
+pub const TRIANGLE_TABLE_WIDTH: usize = 16;
+
+pub type TriangleTable = [[i32; TRIANGLE_TABLE_WIDTH]; 256];
+
+// * Corner offsets, numbered like Paul Bourke's tables
+pub const CORNERS: [UVec3; 8] = [
+    UVec3::new(0, 0, 0),
+    UVec3::new(1, 0, 0),
+    UVec3::new(1, 1, 0),
+    UVec3::new(0, 1, 0),
+    UVec3::new(0, 0, 1),
+    UVec3::new(1, 0, 1),
+    UVec3::new(1, 1, 1),
+    UVec3::new(0, 1, 1),
+];
+// * Corner pairs of the twelve cube edges
+pub const EDGES: [[usize; 2]; 12] = [
+    [0, 1],
+    [1, 2],
+    [2, 3],
+    [3, 0],
+    [4, 5],
+    [5, 6],
+    [6, 7],
+    [7, 4],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+// * Corners of the six faces in cyclic order
+const FACES: [[usize; 4]; 6] = [
+    [0, 1, 2, 3],
+    [4, 5, 6, 7],
+    [0, 1, 5, 4],
+    [1, 2, 6, 5],
+    [2, 3, 7, 6],
+    [3, 0, 4, 7],
+];
+
+fn edge_between(a: usize, b: usize) -> usize {
+    EDGES
+        .iter()
+        .position(|&[c0, c1]| (c0, c1) == (a, b) || (c0, c1) == (b, a))
+        .unwrap()
+}
+
+fn share_face(a: usize, b: usize) -> bool {
+    FACES.iter().any(|face| {
+        let on_face = |e: usize| EDGES[e].iter().all(|c| face.contains(c));
+        on_face(a) && on_face(b)
+    })
+}
+
+// * Ear clipping that never cuts a diagonal across a cube face. Such a diagonal
+// * would be created by the neighbouring cube too and break the mesh there.
+fn triangulate(polygon: &[usize]) -> Option<Vec<[usize; 3]>> {
+    let n = polygon.len();
+    if n == 3 {
+        return Some(vec![[polygon[0], polygon[1], polygon[2]]]);
+    }
+    (0..n).find_map(|i| {
+        let (prev, ear, next) = (polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n]);
+        if share_face(prev, next) {
+            return None;
+        }
+        let mut rest = polygon.to_vec();
+        rest.remove(i);
+        let mut triangles = triangulate(&rest)?;
+        triangles.push([prev, ear, next]);
+        Some(triangles)
+    })
+}
+
+// * Builds the marching cubes case table. Instead of hard-coding it, every case is
+// * polygonised from its faces: sign changes on a face are joined into segments
+// * (ambiguous faces keep the inside corners separated, which is consistent between
+// * neighbouring cubes), segments are chained into loops and each loop is split
+// * into triangles. Built on first use and shared by every extraction after that.
+pub fn triangle_table() -> &'static TriangleTable {
+    static TABLE: OnceLock<TriangleTable> = OnceLock::new();
+    TABLE.get_or_init(build_triangle_table)
+}
+
+fn build_triangle_table() -> TriangleTable {
+    let mut table = [[-1; TRIANGLE_TABLE_WIDTH]; 256];
+    for (case, row) in table.iter_mut().enumerate() {
+        let inside = |corner: usize| case & (1 << corner) != 0;
+
+        // * DIRECTED SEGMENTS ON THE FACES
+        // * Each segment is directed so the inside corner it cuts off lies to its right
+        // * when looking at the face from outside the cube. Chaining them then yields loops
+        // * wound counter-clockwise when seen from outside the surface.
+        let corner = |c: usize| CORNERS[c].as_vec3();
+        let midpoint = |e: usize| (corner(EDGES[e][0]) + corner(EDGES[e][1])) * 0.5;
+        let mut next = [None; 12];
+        for face in FACES {
+            let face_edges: [usize; 4] =
+                std::array::from_fn(|k| edge_between(face[k], face[(k + 1) % 4]));
+            let crossing: Vec<usize> = (0..4)
+                .filter(|&k| inside(face[k]) != inside(face[(k + 1) % 4]))
+                .collect();
+            let segments = match crossing.len() {
+                2 => {
+                    let inside_corner = face.into_iter().find(|&c| inside(c)).unwrap();
+                    vec![(
+                        face_edges[crossing[0]],
+                        face_edges[crossing[1]],
+                        inside_corner,
+                    )]
+                }
+                4 => {
+                    // * Pair the two edges around each inside corner
+                    let k = (0..4).find(|&k| inside(face[k])).unwrap();
+                    vec![
+                        (face_edges[(k + 3) % 4], face_edges[k], face[k]),
+                        (
+                            face_edges[(k + 1) % 4],
+                            face_edges[(k + 2) % 4],
+                            face[(k + 2) % 4],
+                        ),
+                    ]
+                }
+                _ => vec![],
+            };
+            let face_normal =
+                face.iter().map(|&c| corner(c)).sum::<Vec3>() * 0.25 - Vec3::splat(0.5);
+            for (a, b, inside_corner) in segments {
+                let side = (midpoint(b) - midpoint(a))
+                    .cross(corner(inside_corner) - midpoint(a))
+                    .dot(face_normal);
+                if side < 0. {
+                    next[a] = Some(b);
+                } else {
+                    next[b] = Some(a);
+                }
+            }
+        }
+
+        // * CHAIN SEGMENTS INTO LOOPS
+        let mut visited = [false; 12];
+        let mut entries = vec![];
+        for start in 0..12 {
+            if visited[start] || next[start].is_none() {
+                continue;
+            }
+            let mut polygon = vec![];
+            let mut current = start;
+            while !visited[current] {
+                visited[current] = true;
+                polygon.push(current);
+                current = next[current].unwrap();
+            }
+
+            for triangle in triangulate(&polygon).expect("loop without a valid triangulation") {
+                entries.extend(triangle);
+            }
+        }
+        assert!(entries.len() < TRIANGLE_TABLE_WIDTH);
+        for (slot, edge) in row.iter_mut().zip(entries) {
+            *slot = edge as i32;
+        }
+    }
+    table
+}
+
+// * Regular grid of sample nodes around the particles
+#[derive(Clone, Copy, Debug)]
+pub struct MeshGrid {
+    pub origin: Vec3,
+    pub cell_size: f32,
+    // * Number of nodes per axis
+    pub dims: UVec3,
+}
+
+impl MeshGrid {
+    // * Covers `bounds`, e.g. from `particle_bounds`, grown by `padding`
+    pub fn fit(bounds: Option<(Vec3, Vec3)>, padding: f32, cell_size: f32) -> Self {
+        let (min, max) = match bounds {
+            Some((min, max)) => (min - padding, max + padding),
+            None => (Vec3::ZERO, Vec3::ZERO),
+        };
+        let extent = max - min;
+        let cell_size = cell_size.max(extent.max_element() / (MAX_MESH_DIM - 1) as f32);
+        let dims = ((extent / cell_size).ceil().as_uvec3() + 1)
+            .clamp(UVec3::splat(2), UVec3::splat(MAX_MESH_DIM));
+        Self {
+            origin: min,
+            cell_size,
+            dims,
+        }
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        (self.dims.x * self.dims.y * self.dims.z) as usize
+    }
+
+    pub fn node_index(&self, node: UVec3) -> usize {
+        (node.x + self.dims.x * (node.y + self.dims.y * node.z)) as usize
+    }
+
+    pub fn node_pos(&self, node: UVec3) -> Vec3 {
+        self.origin + node.as_vec3() * self.cell_size
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub indices: Vec<u32>,
+}
+
+impl Mesh {
+    // * Samples the same log-sum-exp field as draw.wgsl on a grid around the particles
+    // * and polygonises the `radius` isosurface.
+    pub fn extract(positions: &[Vec3], config: &Config, cell_size: f32) -> Self {
+        let cutoff = config.influence_range();
+        let grid = MeshGrid::fit(particle_bounds(positions), cutoff, cell_size);
+        let field = sample_field(positions, &grid, config.alpha(), cutoff);
+        polygonise(&grid, &field, config.radius(), triangle_table())
+    }
+
+    // * Flattens the indexed mesh into one entry per triangle corner
+    pub fn triangle_vertices(&self) -> impl Iterator<Item = (Vec3, Vec3)> + '_ {
+        self.indices
+            .iter()
+            .map(|&i| (self.positions[i as usize], self.normals[i as usize]))
+    }
+
+    pub fn write_obj(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for p in &self.positions {
+            writeln!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+        }
+        for n in &self.normals {
+            writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+            writeln!(writer, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        }
+        writer.flush()
+    }
+}
+
+// * Log-sum-exp at every grid node, only over particles within `cutoff`.
+// * Nodes without any particle in range get the cutoff as a lower bound.
+pub fn sample_field(positions: &[Vec3], grid: &MeshGrid, alpha: f32, cutoff: f32) -> Vec<f32> {
+    let mut sums = vec![0f32; grid.num_nodes()];
+    let max_node = grid.dims.as_ivec3() - 1;
+    for &p in positions {
+        let lo = ((p - cutoff - grid.origin) / grid.cell_size)
+            .ceil()
+            .as_ivec3()
+            .max(glam::IVec3::ZERO);
+        let hi = ((p + cutoff - grid.origin) / grid.cell_size)
+            .floor()
+            .as_ivec3()
+            .min(max_node);
+        for z in lo.z..=hi.z {
+            for y in lo.y..=hi.y {
+                for x in lo.x..=hi.x {
+                    let node = UVec3::new(x as u32, y as u32, z as u32);
+                    let dist = grid.node_pos(node).distance(p);
+                    if dist < cutoff {
+                        sums[grid.node_index(node)] += (alpha * dist).exp();
+                    }
+                }
+            }
+        }
+    }
+    sums.into_iter()
+        .map(|sum| if sum > 0. { sum.ln() / alpha } else { cutoff })
+        .collect()
+}
+
+fn gradient(grid: &MeshGrid, field: &[f32], node: UVec3) -> Vec3 {
+    let value = |n: UVec3| field[grid.node_index(n)];
+    let axis = |i: usize| {
+        let mut lo = node;
+        let mut hi = node;
+        lo[i] = lo[i].saturating_sub(1);
+        hi[i] = (hi[i] + 1).min(grid.dims[i] - 1);
+        (value(hi) - value(lo)) / ((hi[i] - lo[i]) as f32 * grid.cell_size)
+    };
+    Vec3::new(axis(0), axis(1), axis(2))
+}
+
+// * Marching cubes over a sampled field, sharing vertices between neighbouring cells.
+// * Nodes with a value below `iso` are inside.
+pub fn polygonise(grid: &MeshGrid, field: &[f32], iso: f32, table: &TriangleTable) -> Mesh {
+    let mut mesh = Mesh::default();
+    // * (lower node index, axis) -> vertex index
+    let mut edge_vertices: HashMap<(usize, usize), u32> = HashMap::new();
+    let cells = grid.dims - 1;
+    for z in 0..cells.z {
+        for y in 0..cells.y {
+            for x in 0..cells.x {
+                let cell = UVec3::new(x, y, z);
+                let case = CORNERS.iter().enumerate().fold(0, |case, (i, &c)| {
+                    if field[grid.node_index(cell + c)] < iso {
+                        case | (1 << i)
+                    } else {
+                        case
+                    }
+                });
+                for &edge in table[case].iter().take_while(|&&e| e >= 0) {
+                    let [a, b] = EDGES[edge as usize].map(|c| cell + CORNERS[c]);
+                    let (lo, hi) = if grid.node_index(a) < grid.node_index(b) {
+                        (a, b)
+                    } else {
+                        (b, a)
+                    };
+                    let axis = (hi - lo).to_array().iter().position(|&d| d == 1).unwrap();
+                    let index = *edge_vertices
+                        .entry((grid.node_index(lo), axis))
+                        .or_insert_with(|| {
+                            let v0 = field[grid.node_index(lo)];
+                            let v1 = field[grid.node_index(hi)];
+                            let t = ((iso - v0) / (v1 - v0)).clamp(0., 1.);
+                            mesh.positions
+                                .push(grid.node_pos(lo).lerp(grid.node_pos(hi), t));
+                            mesh.normals.push(
+                                gradient(grid, field, lo)
+                                    .lerp(gradient(grid, field, hi), t)
+                                    .normalize_or_zero(),
+                            );
+                            mesh.positions.len() as u32 - 1
+                        });
+                    mesh.indices.push(index);
+                }
+            }
+        }
+    }
+    mesh
+}
@@ -0,0 +1,205 @@
+// * GPU marching cubes over the log-sum-exp field.
+// * Passes: sample_field -> extract_triangles -> clamp_vertex_count
+// * The vertex count is written straight into the indirect draw arguments.
+
+// * Same as PARTICLE_BOUNDARY in simulation.rs, stored in the w of every position
+const PARTICLE_BOUNDARY = 1.;
+
+struct Grid {
+    origin: vec3<f32>,
+    cell_size: f32,
+    dims: vec3<u32>,
+    cutoff: f32,
+    num_particles: u32,
+    enabled: u32,
+}
+
+struct MeshParams {
+    origin: vec3<f32>,
+    cell_size: f32,
+    // * Number of nodes per axis
+    dims: vec3<u32>,
+    iso: f32,
+    alpha: f32,
+    cutoff: f32,
+    max_vertices: u32,
+    // * Whether the boundary particles are part of the field
+    boundary: u32,
+}
+
+struct MeshVertex {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+}
+
+struct DrawArgs {
+    vertex_count: atomic<u32>,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+}
+
+@group(0) @binding(0)
+var<storage, read> positions: array<vec4<f32>>;
+
+@group(1) @binding(0)
+var<uniform> grid: Grid;
+@group(1) @binding(1)
+var<storage, read> cell_counts: array<u32>;
+@group(1) @binding(2)
+var<storage, read> cell_starts: array<u32>;
+@group(1) @binding(3)
+var<storage, read> sorted_indices: array<u32>;
+
+@group(2) @binding(0)
+var<uniform> mesh: MeshParams;
+// * 256 cases with 16 edge indices each, -1 terminated
+@group(2) @binding(1)
+var<uniform> triangle_table: array<vec4<i32>, 1024>;
+@group(2) @binding(2)
+var<storage, read_write> field: array<f32>;
+@group(2) @binding(3)
+var<storage, read_write> vertices: array<MeshVertex>;
+@group(2) @binding(4)
+var<storage, read_write> draw_args: DrawArgs;
+
+// * Corner and edge numbering matches marching_cubes.rs
+var<private> corners: array<vec3<u32>, 8> = array<vec3<u32>, 8>(
+    vec3<u32>(0u, 0u, 0u),
+    vec3<u32>(1u, 0u, 0u),
+    vec3<u32>(1u, 1u, 0u),
+    vec3<u32>(0u, 1u, 0u),
+    vec3<u32>(0u, 0u, 1u),
+    vec3<u32>(1u, 0u, 1u),
+    vec3<u32>(1u, 1u, 1u),
+    vec3<u32>(0u, 1u, 1u),
+);
+var<private> edges: array<vec2<u32>, 12> = array<vec2<u32>, 12>(
+    vec2<u32>(0u, 1u),
+    vec2<u32>(1u, 2u),
+    vec2<u32>(2u, 3u),
+    vec2<u32>(3u, 0u),
+    vec2<u32>(4u, 5u),
+    vec2<u32>(5u, 6u),
+    vec2<u32>(6u, 7u),
+    vec2<u32>(7u, 4u),
+    vec2<u32>(0u, 4u),
+    vec2<u32>(1u, 5u),
+    vec2<u32>(2u, 6u),
+    vec2<u32>(3u, 7u),
+);
+
+fn node_index(node: vec3<u32>) -> u32 {
+    return node.x + mesh.dims.x * (node.y + mesh.dims.y * node.z);
+}
+
+fn node_pos(node: vec3<u32>) -> vec3<f32> {
+    return mesh.origin + vec3<f32>(node) * mesh.cell_size;
+}
+
+fn grid_cell(pos: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor((pos - grid.origin) / grid.cell_size));
+}
+
+fn grid_cell_index(cell: vec3<i32>) -> u32 {
+    return u32(cell.x) + grid.dims.x * (u32(cell.y) + grid.dims.y * u32(cell.z));
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn sample_field(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id >= mesh.dims) {
+        return;
+    }
+    let pos = node_pos(id);
+    let lo = max(grid_cell(pos - mesh.cutoff), vec3<i32>(0));
+    let hi = min(grid_cell(pos + mesh.cutoff), vec3<i32>(grid.dims) - 1);
+
+    var res = 0.;
+    for (var z = lo.z; z <= hi.z; z++) {
+        for (var y = lo.y; y <= hi.y; y++) {
+            for (var x = lo.x; x <= hi.x; x++) {
+                let cell = grid_cell_index(vec3<i32>(x, y, z));
+                let start = cell_starts[cell];
+                for (var k = start; k < start + cell_counts[cell]; k++) {
+                    let particle = positions[sorted_indices[k]];
+                    if mesh.boundary == 0u && particle.w > 0.5 * PARTICLE_BOUNDARY {
+                        continue;
+                    }
+                    let dist = distance(particle.xyz, pos);
+                    if dist < mesh.cutoff {
+                        res += exp(mesh.alpha * dist);
+                    }
+                }
+            }
+        }
+    }
+    // * No particle in range, the cutoff is a lower bound for the field
+    field[node_index(id)] = select(mesh.cutoff, log(res) / mesh.alpha, res > 0.);
+}
+
+fn gradient(node: vec3<u32>) -> vec3<f32> {
+    var grad = vec3<f32>(0.);
+    for (var i = 0; i < 3; i++) {
+        var lo = node;
+        var hi = node;
+        lo[i] = max(lo[i], 1u) - 1u;
+        hi[i] = min(hi[i] + 1u, mesh.dims[i] - 1u);
+        grad[i] = (field[node_index(hi)] - field[node_index(lo)]) / (f32(hi[i] - lo[i]) * mesh.cell_size);
+    }
+    return grad;
+}
+
+fn edge_vertex(cell: vec3<u32>, edge: u32) -> MeshVertex {
+    let a = cell + corners[edges[edge].x];
+    let b = cell + corners[edges[edge].y];
+    let v0 = field[node_index(a)];
+    let v1 = field[node_index(b)];
+    let t = clamp((mesh.iso - v0) / (v1 - v0), 0., 1.);
+    let position = mix(node_pos(a), node_pos(b), t);
+    let g = mix(gradient(a), gradient(b), t);
+    // * Flat field, e.g. deep inside the fluid, has no normal
+    var normal = vec3<f32>(0.);
+    if length(g) > 0. {
+        normal = normalize(g);
+    }
+    return MeshVertex(vec4<f32>(position, 1.), vec4<f32>(normal, 0.));
+}
+
+fn table_entry(case_index: u32, k: u32) -> i32 {
+    return triangle_table[case_index * 4u + k / 4u][k % 4u];
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn extract_triangles(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id + 1u >= mesh.dims) {
+        return;
+    }
+    var case_index = 0u;
+    for (var i = 0u; i < 8u; i++) {
+        if field[node_index(id + corners[i])] < mesh.iso {
+            case_index |= 1u << i;
+        }
+    }
+    if case_index == 0u || case_index == 255u {
+        return;
+    }
+    for (var k = 0u; k < 15u; k += 3u) {
+        if table_entry(case_index, k) < 0 {
+            break;
+        }
+        let slot = atomicAdd(&draw_args.vertex_count, 3u);
+        if slot + 3u > mesh.max_vertices {
+            return;
+        }
+        for (var j = 0u; j < 3u; j++) {
+            vertices[slot + j] = edge_vertex(id, u32(table_entry(case_index, k + j)));
+        }
+    }
+}
+
+// * Drops the triangles that did not fit into the vertex buffer
+@compute @workgroup_size(1)
+fn clamp_vertex_count() {
+    let count = atomicLoad(&draw_args.vertex_count);
+    atomicStore(&draw_args.vertex_count, min(count, mesh.max_vertices));
+}
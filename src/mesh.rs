@@ -0,0 +1,421 @@
+use glam::{UVec3, Vec3};
+use wgpu::{
+    include_wgsl, util::DeviceExt, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutEntry, Buffer, BufferBindingType, BufferDescriptor, BufferUsages, Color,
+    ColorTargetState, ColorWrites, CommandEncoder, CommandEncoderDescriptor, CompareFunction,
+    ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, DepthStencilState, Device,
+    Extent3d, FragmentState, MultisampleState, PipelineLayoutDescriptor, PrimitiveState,
+    PrimitiveTopology, Queue, RenderPipeline, RenderPipelineDescriptor, ShaderStages,
+    SurfaceConfiguration, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+    TextureView, TextureViewDescriptor, VertexBufferLayout, VertexState,
+};
+
+use crate::{
+    config::Config,
+    grid::GridState,
+    marching_cubes::{triangle_table, Mesh, MeshGrid, MAX_MESH_DIM, MESH_CELL_SIZE},
+    simulation::SimulationState,
+};
+
+const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+// * MULTIPLE OF 3, TRIANGLES BEYOND THIS ARE DROPPED
+const MAX_MESH_VERTICES: u32 = 3 * (1 << 19);
+const WORKGROUP_SIZE: u32 = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MeshBackend {
+    Gpu,
+    Cpu,
+}
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, Default)]
+struct MeshUniform {
+    origin: Vec3,
+    cell_size: f32,
+    dims: UVec3,
+    iso: f32,
+    alpha: f32,
+    cutoff: f32,
+    max_vertices: u32,
+    // * Whether the boundary particles are part of the field
+    boundary: u32,
+}
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+pub struct MeshVertex {
+    position: [f32; 4],
+    normal: [f32; 4],
+}
+
+impl MeshVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+    pub fn desc() -> VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<MeshVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+struct MeshPipelines {
+    sample_field: ComputePipeline,
+    extract_triangles: ComputePipeline,
+    clamp_vertex_count: ComputePipeline,
+}
+
+// * Surface mesh of the log-sum-exp field, extracted with marching cubes either on the
+// * GPU (straight into the vertex buffer) or on the CPU, and drawn with a raster pipeline.
+pub struct MeshState {
+    pub backend: MeshBackend,
+    // * Set when the particles moved since the last extraction
+    pub dirty: bool,
+    uniform_buffer: Buffer,
+    vertex_buffer: Buffer,
+    draw_args: Buffer,
+    compute_bind_group: BindGroup,
+    pipelines: MeshPipelines,
+    render_pipeline: RenderPipeline,
+    depth_view: TextureView,
+}
+
+impl MeshState {
+    pub fn create_mesh(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        camera_bind_group_layout: &BindGroupLayout,
+        simulation_bind_group_layout: &BindGroupLayout,
+        grid_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Mesh Buffer"),
+            size: std::mem::size_of::<MeshUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let table = triangle_table();
+        let table_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Triangle Table Buffer"),
+            contents: bytemuck::cast_slice(table),
+            usage: BufferUsages::UNIFORM,
+        });
+        let field_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Mesh Field Buffer"),
+            size: (MAX_MESH_DIM.pow(3) as usize * std::mem::size_of::<f32>()) as u64,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let vertex_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Mesh Vertex Buffer"),
+            size: (MAX_MESH_VERTICES as usize * std::mem::size_of::<MeshVertex>()) as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let draw_args = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesh Draw Args Buffer"),
+            contents: bytemuck::cast_slice(&[0u32, 1, 0, 0]),
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT | BufferUsages::COPY_DST,
+        });
+
+        let entry = |binding, ty| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let storage = BufferBindingType::Storage { read_only: false };
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Mesh Compute Bind Group Layout"),
+                entries: &[
+                    entry(0, BufferBindingType::Uniform),
+                    entry(1, BufferBindingType::Uniform),
+                    entry(2, storage),
+                    entry(3, storage),
+                    entry(4, storage),
+                ],
+            });
+        let compute_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Mesh Compute Bind Group"),
+            layout: &compute_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: table_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: field_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: vertex_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: draw_args.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipelines = Self::create_compute_pipelines(
+            device,
+            &[
+                simulation_bind_group_layout,
+                grid_bind_group_layout,
+                &compute_bind_group_layout,
+            ],
+        );
+        let render_pipeline =
+            Self::create_render_pipeline(device, config, &[camera_bind_group_layout]);
+        let depth_view = Self::create_depth_view(device, config.width, config.height);
+
+        Self {
+            backend: MeshBackend::Gpu,
+            dirty: true,
+            uniform_buffer,
+            vertex_buffer,
+            draw_args,
+            compute_bind_group,
+            pipelines,
+            render_pipeline,
+            depth_view,
+        }
+    }
+
+    pub fn extract(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        simulation_state: &mut SimulationState,
+        grid: &GridState,
+        config: &Config,
+    ) {
+        match self.backend {
+            MeshBackend::Gpu => self.extract_gpu(device, queue, simulation_state, grid, config),
+            MeshBackend::Cpu => self.upload(
+                queue,
+                &Mesh::extract(
+                    &simulation_state.surface_positions(config.surface_includes_boundary()),
+                    config,
+                    MESH_CELL_SIZE,
+                ),
+            ),
+        }
+        self.dirty = false;
+    }
+
+    fn extract_gpu(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        simulation_state: &SimulationState,
+        grid: &GridState,
+        config: &Config,
+    ) {
+        let cutoff = config.influence_range();
+        let mesh_grid = MeshGrid::fit(simulation_state.bounds(), cutoff, MESH_CELL_SIZE);
+        let uniform = MeshUniform {
+            origin: mesh_grid.origin,
+            cell_size: mesh_grid.cell_size,
+            dims: mesh_grid.dims,
+            iso: config.radius(),
+            alpha: config.alpha(),
+            cutoff,
+            max_vertices: MAX_MESH_VERTICES,
+            boundary: config.surface_includes_boundary() as u32,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        queue.write_buffer(&self.draw_args, 0, bytemuck::cast_slice(&[0u32, 1, 0, 0]));
+
+        let groups = |dims: UVec3| (dims + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+        let node_groups = groups(mesh_grid.dims);
+        let cell_groups = groups(mesh_grid.dims - 1);
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Mesh Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Marching Cubes Pass"),
+            });
+            compute_pass.set_bind_group(0, &simulation_state.bind_group, &[]);
+            compute_pass.set_bind_group(1, &grid.bind_group, &[]);
+            compute_pass.set_bind_group(2, &self.compute_bind_group, &[]);
+
+            compute_pass.set_pipeline(&self.pipelines.sample_field);
+            compute_pass.dispatch_workgroups(node_groups.x, node_groups.y, node_groups.z);
+            compute_pass.set_pipeline(&self.pipelines.extract_triangles);
+            compute_pass.dispatch_workgroups(cell_groups.x, cell_groups.y, cell_groups.z);
+            compute_pass.set_pipeline(&self.pipelines.clamp_vertex_count);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+
+    // * Replaces the drawn mesh with one extracted on the CPU
+    pub fn upload(&self, queue: &Queue, mesh: &Mesh) {
+        let vertices: Vec<MeshVertex> = mesh
+            .triangle_vertices()
+            .take(MAX_MESH_VERTICES as usize)
+            .map(|(p, n)| MeshVertex {
+                position: p.extend(1.).into(),
+                normal: n.extend(0.).into(),
+            })
+            .collect();
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        queue.write_buffer(
+            &self.draw_args,
+            0,
+            bytemuck::cast_slice(&[vertices.len() as u32, 1, 0, 0]),
+        );
+    }
+
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.depth_view = Self::create_depth_view(device, width, height);
+    }
+
+    pub fn encode(
+        &self,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        background: Color,
+        camera_bind_group: &BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mesh Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(background),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw_indirect(&self.draw_args, 0);
+    }
+
+    fn create_depth_view(device: &Device, width: u32, height: u32) -> TextureView {
+        device
+            .create_texture(&TextureDescriptor {
+                label: Some("Mesh Depth Buffer"),
+                size: Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: DEPTH_FORMAT,
+                usage: TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&TextureViewDescriptor::default())
+    }
+
+    fn create_compute_pipelines(
+        device: &Device,
+        bind_group_layouts: &[&BindGroupLayout],
+    ) -> MeshPipelines {
+        // * LOAD SHADER
+        let shader = device.create_shader_module(include_wgsl!("marching_cubes.wgsl"));
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Marching Cubes Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+        MeshPipelines {
+            sample_field: pipeline("sample_field"),
+            extract_triangles: pipeline("extract_triangles"),
+            clamp_vertex_count: pipeline("clamp_vertex_count"),
+        }
+    }
+
+    fn create_render_pipeline(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        bind_group_layouts: &[&BindGroupLayout],
+    ) -> RenderPipeline {
+        // * LOAD SHADER
+        let shader = device.create_shader_module(include_wgsl!("mesh.wgsl"));
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Mesh Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Mesh Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[MeshVertex::desc()],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format: config.format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+    }
+}
@@ -0,0 +1,50 @@
+// * Rasterises the extracted surface mesh
+const NEAR: f32 = 0.1;
+const FAR: f32 = 1000.;
+
+// * +X = Right; +Y = Up; +Z = Back
+struct Camera {
+    pos: vec3<f32>,
+    screen_width: f32,
+    dir: vec3<f32>,
+    screen_height: f32,
+    up: vec3<f32>,
+    screen_dist: f32,
+    view_matrix: mat4x4<f32>,
+    inverse_view_matrix: mat4x4<f32>
+}
+
+struct VertexInput {
+    @location(0) position: vec4<f32>,
+    @location(1) normal: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// * Perspective projection matching the ray directions of the ray marcher
+fn project(view_pos: vec3<f32>) -> vec4<f32> {
+    let fx = camera.screen_dist / (camera.screen_width * 0.5);
+    let fy = camera.screen_dist / (camera.screen_height * 0.5);
+    let z = (view_pos.z * FAR + NEAR * FAR) / (NEAR - FAR);
+    return vec4<f32>(view_pos.x * fx, view_pos.y * fy, z, -view_pos.z);
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    let view_pos = (camera.view_matrix * vec4<f32>(model.position.xyz, 1.)).xyz;
+    return VertexOutput(project(view_pos), model.normal.xyz);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // * Same debug look as the ray marcher: world space normal as colour
+    return vec4<f32>(normalize(in.normal), 1.);
+}
@@ -0,0 +1,293 @@
+use std::{collections::HashMap, f32::consts::PI};
+
+use glam::{IVec3, Vec3};
+use sph::sph::Particle;
+
+use crate::{
+    backend::SimulationBackend,
+    scene::{FluidParams, PbfParams},
+};
+
+// * Free space around the initial particles before the domain walls, in smoothing lengths
+const DOMAIN_MARGIN: f32 = 4.;
+
+// * Position based fluids (Macklin & Müller 2013) on the CPU. Immovable particles take part in
+// * the density constraints as boundary but are never moved.
+pub struct Pbf {
+    fluid: FluidParams,
+    params: PbfParams,
+    gravity: Vec3,
+    domain_min: Vec3,
+    domain_max: Vec3,
+    positions: Vec<Vec3>,
+    velocities: Vec<Vec3>,
+    densities: Vec<f32>,
+    masses: Vec<f32>,
+    movable: Vec<bool>,
+}
+
+impl Pbf {
+    pub fn new(
+        particles: Vec<Particle>,
+        fluid: FluidParams,
+        params: PbfParams,
+        gravity: Vec3,
+    ) -> Self {
+        let mut pbf = Self {
+            fluid,
+            params,
+            gravity,
+            domain_min: Vec3::ZERO,
+            domain_max: Vec3::ZERO,
+            positions: vec![],
+            velocities: vec![],
+            densities: vec![],
+            masses: vec![],
+            movable: vec![],
+        };
+        pbf.reset(particles);
+        pbf
+    }
+
+    // * Kernel support, twice the smoothing length like the SPH solvers
+    fn support(&self) -> f32 {
+        2. * self.fluid.smoothing_length
+    }
+
+    // * Poly6 kernel for densities
+    fn kernel(&self, r: f32) -> f32 {
+        let h = self.support();
+        if r >= h {
+            return 0.;
+        }
+        315. / (64. * PI * h.powi(9)) * (h * h - r * r).powi(3)
+    }
+
+    // * Spiky kernel gradient for constraint gradients
+    fn kernel_gradient(&self, r_vec: Vec3) -> Vec3 {
+        let h = self.support();
+        let r = r_vec.length();
+        if r >= h || r < 1.0e-6 {
+            return Vec3::ZERO;
+        }
+        -45. / (PI * h.powi(6)) * (h - r).powi(2) * r_vec / r
+    }
+
+    // * Neighbours within the kernel support, without the particle itself
+    fn neighbours(&self) -> Vec<Vec<usize>> {
+        let support = self.support();
+        let cell = |p: Vec3| (p / support).floor().as_ivec3();
+        let mut cells: HashMap<IVec3, Vec<usize>> = HashMap::new();
+        for (i, p) in self.positions.iter().enumerate() {
+            cells.entry(cell(*p)).or_default().push(i);
+        }
+
+        self.positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let center = cell(*p);
+                let mut neighbours = vec![];
+                for z in -1..=1 {
+                    for y in -1..=1 {
+                        for x in -1..=1 {
+                            let Some(candidates) = cells.get(&(center + IVec3::new(x, y, z)))
+                            else {
+                                continue;
+                            };
+                            neighbours.extend(candidates.iter().copied().filter(|&j| {
+                                j != i && p.distance_squared(self.positions[j]) < support * support
+                            }));
+                        }
+                    }
+                }
+                neighbours
+            })
+            .collect()
+    }
+
+    fn compute_densities(&mut self, neighbours: &[Vec<usize>]) {
+        let self_density = self.kernel(0.);
+        self.densities = (0..self.positions.len())
+            .map(|i| {
+                self.masses[i] * self_density
+                    + neighbours[i]
+                        .iter()
+                        .map(|&j| {
+                            self.masses[j]
+                                * self.kernel(self.positions[i].distance(self.positions[j]))
+                        })
+                        .sum::<f32>()
+            })
+            .collect();
+    }
+
+    // * One Jacobi iteration of the density constraints
+    fn project_densities(&mut self, neighbours: &[Vec<usize>]) {
+        self.compute_densities(neighbours);
+        let rest_density = self.fluid.rest_density;
+        let lambdas: Vec<f32> = (0..self.positions.len())
+            .map(|i| {
+                // * Only compression is corrected, the tensile term keeps the surface together
+                let constraint = (self.densities[i] / rest_density - 1.).max(0.);
+                let mut gradient_i = Vec3::ZERO;
+                let mut sum_gradients = 0.;
+                for &j in &neighbours[i] {
+                    let gradient = self.masses[j] / rest_density
+                        * self.kernel_gradient(self.positions[i] - self.positions[j]);
+                    gradient_i += gradient;
+                    sum_gradients += gradient.length_squared();
+                }
+                sum_gradients += gradient_i.length_squared();
+                -constraint / (sum_gradients + self.params.relaxation)
+            })
+            .collect();
+
+        let corrections: Vec<Vec3> = (0..self.positions.len())
+            .map(|i| {
+                if !self.movable[i] {
+                    return Vec3::ZERO;
+                }
+                neighbours[i]
+                    .iter()
+                    .map(|&j| {
+                        let r_vec = self.positions[i] - self.positions[j];
+                        self.masses[j]
+                            * (lambdas[i] + lambdas[j] + self.tensile_correction(r_vec.length()))
+                            * self.kernel_gradient(r_vec)
+                    })
+                    .sum::<Vec3>()
+                    / rest_density
+            })
+            .collect();
+
+        for (i, correction) in corrections.into_iter().enumerate() {
+            self.positions[i] =
+                (self.positions[i] + correction).clamp(self.domain_min, self.domain_max);
+        }
+    }
+
+    // * Artificial pressure against particle clustering at the free surface
+    fn tensile_correction(&self, r: f32) -> f32 {
+        let reference = self.kernel(self.params.tensile_distance * self.support());
+        -self.params.tensile_strength * (self.kernel(r) / reference).powi(self.params.tensile_power)
+    }
+
+    // * Curl of the velocity field, per particle
+    fn vorticities(&self, neighbours: &[Vec<usize>]) -> Vec<Vec3> {
+        (0..self.positions.len())
+            .map(|i| {
+                neighbours[i]
+                    .iter()
+                    .map(|&j| {
+                        let gradient = self.kernel_gradient(self.positions[i] - self.positions[j]);
+                        self.masses[j] / self.densities[j]
+                            * (self.velocities[j] - self.velocities[i]).cross(gradient)
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    // * Adds back the rotation lost to the position projection
+    fn confine_vorticity(&mut self, neighbours: &[Vec<usize>], dt: f32) {
+        if self.params.vorticity <= 0. {
+            return;
+        }
+        let vorticities = self.vorticities(neighbours);
+        for i in (0..self.positions.len()).filter(|&i| self.movable[i]) {
+            let location: Vec3 = neighbours[i]
+                .iter()
+                .map(|&j| {
+                    let gradient = self.kernel_gradient(self.positions[i] - self.positions[j]);
+                    self.masses[j] / self.densities[j] * vorticities[j].length() * gradient
+                })
+                .sum();
+            let Some(normal) = location.try_normalize() else {
+                continue;
+            };
+            self.velocities[i] += dt * self.params.vorticity * normal.cross(vorticities[i]);
+        }
+    }
+
+    // * XSPH viscosity, blends each velocity towards its fluid neighbours
+    fn apply_xsph(&mut self, neighbours: &[Vec<usize>]) {
+        let velocities: Vec<Vec3> = (0..self.positions.len())
+            .map(|i| {
+                if !self.movable[i] {
+                    return self.velocities[i];
+                }
+                let blend: Vec3 = neighbours[i]
+                    .iter()
+                    .filter(|&&j| self.movable[j])
+                    .map(|&j| {
+                        let r = self.positions[i].distance(self.positions[j]);
+                        self.masses[j] / self.densities[j]
+                            * (self.velocities[j] - self.velocities[i])
+                            * self.kernel(r)
+                    })
+                    .sum();
+                self.velocities[i] + self.params.xsph * blend
+            })
+            .collect();
+        self.velocities = velocities;
+    }
+}
+
+impl SimulationBackend for Pbf {
+    fn step(&mut self, dt: f32) {
+        let previous = self.positions.clone();
+        // * PREDICT
+        for i in (0..self.positions.len()).filter(|&i| self.movable[i]) {
+            self.velocities[i] += dt * self.gravity;
+            self.positions[i] = (self.positions[i] + dt * self.velocities[i])
+                .clamp(self.domain_min, self.domain_max);
+        }
+
+        // * PROJECT
+        let neighbours = self.neighbours();
+        for _ in 0..self.params.iterations {
+            self.project_densities(&neighbours);
+        }
+
+        // * UPDATE VELOCITIES
+        for i in (0..self.positions.len()).filter(|&i| self.movable[i]) {
+            self.velocities[i] = (self.positions[i] - previous[i]) / dt;
+        }
+        self.compute_densities(&neighbours);
+        self.confine_vorticity(&neighbours, dt);
+        self.apply_xsph(&neighbours);
+    }
+
+    fn positions(&self) -> Vec<Vec3> {
+        self.positions.clone()
+    }
+
+    fn velocities(&self) -> Option<Vec<Vec3>> {
+        Some(self.velocities.clone())
+    }
+
+    fn densities(&self) -> Option<Vec<f32>> {
+        Some(self.densities.clone())
+    }
+
+    fn num_particles(&self) -> usize {
+        self.positions.len()
+    }
+
+    fn reset(&mut self, particles: Vec<Particle>) {
+        self.positions = particles.iter().map(|p| p.pos.into()).collect();
+        self.velocities = particles.iter().map(|p| p.vel.into()).collect();
+        self.masses = particles.iter().map(|p| p.mass).collect();
+        self.movable = particles.iter().map(|p| p.movable).collect();
+        self.densities = vec![self.fluid.rest_density; particles.len()];
+
+        let (min, max) = self.positions.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), p| (min.min(*p), max.max(*p)),
+        );
+        let margin = DOMAIN_MARGIN * self.fluid.smoothing_length;
+        self.domain_min = min - margin;
+        self.domain_max = max + margin;
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use glam::Vec3;
+use sph::sph::Particle;
+
+use crate::backend::SimulationBackend;
+
+const MAGIC: [u8; 8] = *b"FLUIDREC";
+// * Bump whenever the file layout changes
+pub const RECORDING_VERSION: u32 = 1;
+
+// * Little endian, followed by one movable flag (u32) per particle and then the frames, each
+// * `num_particles` positions in simulation units as [f32; 3]
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    num_particles: u32,
+    // * Simulated time between two frames
+    dt: f32,
+    _padding: u32,
+}
+
+// * Streams the positions of every solver step to a file
+pub struct Recorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    frames: usize,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, movable: &[bool], dt: f32) -> Result<Self, String> {
+        let file = File::create(path)
+            .map_err(|e| format!("failed to create recording {}: {e}", path.display()))?;
+        let mut recorder = Self {
+            writer: BufWriter::new(file),
+            path: path.to_path_buf(),
+            frames: 0,
+        };
+        let header = Header {
+            magic: MAGIC,
+            version: RECORDING_VERSION,
+            num_particles: movable.len() as u32,
+            dt,
+            _padding: 0,
+        };
+        let flags: Vec<u32> = movable.iter().map(|&m| m as u32).collect();
+        recorder.write(bytemuck::bytes_of(&header))?;
+        recorder.write(bytemuck::cast_slice(&flags))?;
+        Ok(recorder)
+    }
+
+    pub fn write_frame(&mut self, positions: &[Vec3]) -> Result<(), String> {
+        let frame: Vec<[f32; 3]> = positions.iter().map(|p| p.to_array()).collect();
+        self.write(bytemuck::cast_slice(&frame))?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    // * Flushes the file, returns the number of frames written
+    pub fn finish(mut self) -> Result<usize, String> {
+        self.writer
+            .flush()
+            .map_err(|e| format!("failed to write recording {}: {e}", self.path.display()))?;
+        Ok(self.frames)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.writer
+            .write_all(bytes)
+            .map_err(|e| format!("failed to write recording {}: {e}", self.path.display()))
+    }
+}
+
+// * A recording opened for random access to its frames
+pub struct Recording {
+    file: File,
+    path: PathBuf,
+    movable: Vec<bool>,
+    frames: usize,
+    // * Simulated time between two frames
+    dt: f32,
+}
+
+impl Recording {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let error = |e: String| format!("invalid recording {}: {e}", path.display());
+        let mut file = File::open(path)
+            .map_err(|e| format!("failed to open recording {}: {e}", path.display()))?;
+        let mut header = Header {
+            magic: [0; 8],
+            version: 0,
+            num_particles: 0,
+            dt: 0.,
+            _padding: 0,
+        };
+        file.read_exact(bytemuck::bytes_of_mut(&mut header))
+            .map_err(|e| error(e.to_string()))?;
+        if header.magic != MAGIC {
+            return Err(error("not a recording file".into()));
+        }
+        if header.version != RECORDING_VERSION {
+            return Err(error(format!(
+                "unsupported version {}, expected {RECORDING_VERSION}",
+                header.version
+            )));
+        }
+        if header.num_particles == 0 {
+            return Err(error("recording has no particles".into()));
+        }
+        if !(header.dt > 0. && header.dt.is_finite()) {
+            return Err(error(format!("invalid time step {}", header.dt)));
+        }
+        // * Checked before allocating, a corrupt header can claim billions of particles
+        let length = file.metadata().map_err(|e| error(e.to_string()))?.len();
+        let flags_size = header.num_particles as u64 * std::mem::size_of::<u32>() as u64;
+        if std::mem::size_of::<Header>() as u64 + flags_size > length {
+            return Err(error(format!(
+                "file is too short for {} particles",
+                header.num_particles
+            )));
+        }
+        let mut flags = vec![0u32; header.num_particles as usize];
+        file.read_exact(bytemuck::cast_slice_mut(&mut flags))
+            .map_err(|e| error(e.to_string()))?;
+
+        let mut recording = Self {
+            file,
+            path: path.to_path_buf(),
+            movable: flags.iter().map(|&f| f != 0).collect(),
+            frames: 0,
+            dt: header.dt,
+        };
+        // * A partly written last frame, e.g. from a crash, is ignored
+        recording.frames = ((length - recording.frames_offset()) / recording.frame_size()) as usize;
+        if recording.frames == 0 {
+            return Err(error("recording has no frames".into()));
+        }
+        Ok(recording)
+    }
+
+    pub fn num_particles(&self) -> usize {
+        self.movable.len()
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    pub fn dt(&self) -> f32 {
+        self.dt
+    }
+
+    pub fn read_frame(&mut self, frame: usize) -> Result<Vec<Vec3>, String> {
+        let offset = self.frames_offset() + frame as u64 * self.frame_size();
+        let mut positions = vec![[0f32; 3]; self.num_particles()];
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| {
+                self.file
+                    .read_exact(bytemuck::cast_slice_mut(&mut positions))
+            })
+            .map_err(|e| {
+                format!(
+                    "failed to read frame {frame} of {}: {e}",
+                    self.path.display()
+                )
+            })?;
+        Ok(positions.into_iter().map(Vec3::from).collect())
+    }
+
+    // * The first frame as particles, e.g. to size the simulation buffer
+    pub fn particles(&mut self) -> Result<Vec<Particle>, String> {
+        Ok(self
+            .read_frame(0)?
+            .into_iter()
+            .zip(&self.movable)
+            .map(|(pos, &movable)| Particle::new(pos.into(), 1., movable))
+            .collect())
+    }
+
+    fn frames_offset(&self) -> u64 {
+        (std::mem::size_of::<Header>() + self.num_particles() * std::mem::size_of::<u32>()) as u64
+    }
+
+    fn frame_size(&self) -> u64 {
+        (self.num_particles() * std::mem::size_of::<[f32; 3]>()) as u64
+    }
+}
+
+// * Plays a recording back instead of solving, one frame per step
+pub struct Replay {
+    recording: Recording,
+    frame: usize,
+    positions: Vec<Vec3>,
+}
+
+impl Replay {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut recording = Recording::open(path)?;
+        let positions = recording.read_frame(0)?;
+        Ok(Self {
+            recording,
+            frame: 0,
+            positions,
+        })
+    }
+
+    fn show(&mut self, frame: usize) {
+        let frame = frame.min(self.recording.frames() - 1);
+        if frame == self.frame {
+            return;
+        }
+        match self.recording.read_frame(frame) {
+            Ok(positions) => {
+                self.positions = positions;
+                self.frame = frame;
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+impl SimulationBackend for Replay {
+    // * The recording already fixes the time between frames
+    fn step(&mut self, _dt: f32) {
+        self.show(self.frame + 1);
+    }
+
+    fn positions(&self) -> Vec<Vec3> {
+        self.positions.clone()
+    }
+
+    fn num_particles(&self) -> usize {
+        self.recording.num_particles()
+    }
+
+    fn reset(&mut self, _particles: Vec<Particle>) {
+        self.show(0);
+    }
+
+    fn seek(&mut self, to: SeekFrom) {
+        let frame = match to {
+            SeekFrom::Start(frame) => frame as i64,
+            SeekFrom::Current(offset) => self.frame as i64 + offset,
+            SeekFrom::End(offset) => self.recording.frames() as i64 - 1 + offset,
+        };
+        self.show(frame.max(0) as usize);
+        println!("frame {}/{}", self.frame, self.recording.frames() - 1);
+    }
+
+    fn time(&self) -> Option<f64> {
+        Some(self.frame as f64 * self.recording.dt() as f64)
+    }
+}
+
+// * Removes `--replay <file>` from the arguments
+pub fn take_replay_arg(args: &mut Vec<String>) -> Result<Option<PathBuf>, String> {
+    let Some(i) = args.iter().position(|arg| arg == "--replay") else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err("missing value for --replay".into());
+    }
+    let path = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(PathBuf::from(path)))
+}
@@ -1,13 +1,17 @@
 use wgpu::{
     include_wgsl, BindGroup, BindGroupLayout, Buffer, Color, ColorTargetState, ColorWrites,
-    CommandEncoderDescriptor, Device, FragmentState, MultisampleState, PipelineLayoutDescriptor,
-    PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
-    SurfaceConfiguration, TextureViewDescriptor, VertexState,
+    CommandEncoder, CommandEncoderDescriptor, Device, FragmentState, MultisampleState,
+    PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, Queue, RenderPipeline,
+    RenderPipelineDescriptor, SurfaceConfiguration, SurfaceError, TextureView,
+    TextureViewDescriptor, VertexState,
 };
 
 use crate::{
     config::{Config, DrawShaderConfig},
     env::Environment,
+    mesh::MeshState,
+    screen_space::ScreenSpaceRenderer,
+    ui::Overlay,
     vertex::{Vertex, SQUARE},
 };
 
@@ -17,48 +21,111 @@ const BACKGROUND_COLOR: Color = Color {
     b: 0.3,
     a: 1.0,
 };
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RenderMode {
+    // * Sphere trace the log-sum-exp field per pixel
+    RayMarch,
+    // * Splat, filter and shade particle depth in screen space
+    ScreenSpace,
+    // * Rasterise the marching cubes surface mesh
+    Mesh,
+}
+
+impl RenderMode {
+    pub fn next(self) -> Self {
+        match self {
+            RenderMode::RayMarch => RenderMode::ScreenSpace,
+            RenderMode::ScreenSpace => RenderMode::Mesh,
+            RenderMode::Mesh => RenderMode::RayMarch,
+        }
+    }
+}
+
 pub struct RenderState {
     pub vertex_buffer: Buffer,
     pub render_pipeline: RenderPipeline,
     pub config_bind_group: BindGroup,
+    // * Persistent, see `set_config`
+    config_buffer: Buffer,
+    pub screen_space: ScreenSpaceRenderer,
+    pub mesh: MeshState,
+    pub mode: RenderMode,
 }
 impl RenderState {
     pub fn new(
-        env: &Environment,
+        device: &Device,
+        surface_config: &SurfaceConfiguration,
         camera_bind_group_layout: BindGroupLayout,
         simulation_bind_group_layout: BindGroupLayout,
+        grid_bind_group_layout: BindGroupLayout,
         config: &Config,
     ) -> Self {
         // * CREATE VERTEX & INSTANCE BUFFERS
-        let vertex_buffer = Vertex::create_vertex_buffer(&env.device);
+        let vertex_buffer = Vertex::create_vertex_buffer(device);
 
-        let (config_bind_group_layout, config_bind_group) =
-            DrawShaderConfig::from(config).create_bind_group(&env.device);
+        let (config_bind_group_layout, config_bind_group, config_buffer) =
+            DrawShaderConfig::from(config).create_bind_group(device);
+
+        // * CREATE SCREEN SPACE PIPELINES
+        let screen_space = ScreenSpaceRenderer::new(
+            device,
+            surface_config,
+            &camera_bind_group_layout,
+            &config_bind_group_layout,
+            &simulation_bind_group_layout,
+        );
+
+        // * CREATE MESH PIPELINES
+        let mesh = MeshState::create_mesh(
+            device,
+            surface_config,
+            &camera_bind_group_layout,
+            &simulation_bind_group_layout,
+            &grid_bind_group_layout,
+        );
 
         // * CREATE RENDER PIPELINE
         let render_pipeline = Self::create_render_pipeline(
-            &env.device,
-            &env.config,
+            device,
+            surface_config,
             &[
                 &camera_bind_group_layout,
                 &config_bind_group_layout,
                 &simulation_bind_group_layout,
+                &grid_bind_group_layout,
             ],
         );
         Self {
             vertex_buffer,
             render_pipeline,
             config_bind_group,
+            config_buffer,
+            screen_space,
+            mesh,
+            mode: RenderMode::RayMarch,
         }
     }
 
+    pub fn set_config(&self, queue: &Queue, config: &Config) {
+        DrawShaderConfig::from(config).write(queue, &self.config_buffer);
+    }
+
+    // * Recreates the size dependent targets of the render modes
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.screen_space.resize(device, width, height);
+        self.mesh.resize(device, width, height);
+    }
+
     pub fn render_call(
         &self,
         simulation_bind_group: &BindGroup,
         env: &Environment,
         camera_bind_group: &BindGroup,
-    ) {
-        let output = env.surface.get_current_texture().unwrap();
+        grid_bind_group: &BindGroup,
+        num_particles: u32,
+        overlay: &mut Overlay,
+    ) -> Result<(), SurfaceError> {
+        let output = env.surface.get_current_texture()?;
         let mut encoder = env
             .device
             .create_command_encoder(&CommandEncoderDescriptor {
@@ -67,30 +134,76 @@ impl RenderState {
         let view = output
             .texture
             .create_view(&TextureViewDescriptor::default());
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(BACKGROUND_COLOR),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
-            render_pass.set_pipeline(&self.render_pipeline);
+        self.encode(
+            &mut encoder,
+            &view,
+            simulation_bind_group,
+            camera_bind_group,
+            grid_bind_group,
+            num_particles,
+        );
+        // * UI ON TOP
+        overlay.paint(
+            &env.device,
+            &env.queue,
+            &mut encoder,
+            &view,
+            [env.config.width, env.config.height],
+        );
+        env.queue.submit(Some(encoder.finish()));
+        output.present();
+        Ok(())
+    }
+
+    // * Records the current render mode into `view`, shared by the window and headless rendering
+    pub fn encode(
+        &self,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        simulation_bind_group: &BindGroup,
+        camera_bind_group: &BindGroup,
+        grid_bind_group: &BindGroup,
+        num_particles: u32,
+    ) {
+        match self.mode {
+            RenderMode::ScreenSpace => self.screen_space.encode(
+                encoder,
+                view,
+                BACKGROUND_COLOR,
+                &self.vertex_buffer,
+                camera_bind_group,
+                &self.config_bind_group,
+                simulation_bind_group,
+                num_particles,
+            ),
+            RenderMode::Mesh => {
+                self.mesh
+                    .encode(encoder, view, BACKGROUND_COLOR, camera_bind_group)
+            }
+            RenderMode::RayMarch => {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Render Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(BACKGROUND_COLOR),
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+                render_pass.set_pipeline(&self.render_pipeline);
 
-            render_pass.set_bind_group(0, camera_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.config_bind_group, &[]);
-            render_pass.set_bind_group(2, simulation_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass.set_bind_group(0, camera_bind_group, &[]);
+                render_pass.set_bind_group(1, &self.config_bind_group, &[]);
+                render_pass.set_bind_group(2, simulation_bind_group, &[]);
+                render_pass.set_bind_group(3, grid_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
 
-            render_pass.draw(0..SQUARE.len() as u32, 0..1)
+                render_pass.draw(0..SQUARE.len() as u32, 0..1)
+            }
         }
-        env.queue.submit(Some(encoder.finish()));
-        output.present();
     }
 
     fn create_render_pipeline(
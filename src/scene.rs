@@ -0,0 +1,620 @@
+use std::path::{Path, PathBuf};
+
+use glam::Vec3;
+use serde::Deserialize;
+use sph::sph::Particle;
+
+use crate::{
+    attribute::ParticleAttribute,
+    config::{BoundaryMode, Colormap, Config, Light, Shading, MAX_LIGHTS},
+    import::PointSequence,
+    recording::Recording,
+};
+
+// * Used when no `--scene` is given
+const DEFAULT_SCENE: &str = include_str!("../scenes/cube_in_box.toml");
+// * Lattice points a block may span, guards against a spacing that is far too small
+const MAX_BLOCK_LATTICE: f64 = 16_000_000.;
+
+// * Complete description of an experiment: solver parameters, particles, camera and render config
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    #[serde(default)]
+    pub solver: SolverKind,
+    #[serde(default = "default_gravity")]
+    pub gravity: [f32; 3],
+    #[serde(default)]
+    pub fluid: FluidParams,
+    #[serde(default)]
+    pub pbf: PbfParams,
+    #[serde(default)]
+    pub camera: CameraParams,
+    #[serde(default)]
+    pub render: RenderParams,
+    #[serde(default)]
+    pub blocks: Vec<Block>,
+    // * Plays this recording back instead of running the solver, relative to the scene file
+    #[serde(default)]
+    pub replay: Option<PathBuf>,
+    // * Renders point files of another solver instead of running one, see import.rs
+    #[serde(default)]
+    pub import: Option<ImportParams>,
+    // * First frame of the replay or import as (position, movable)
+    #[serde(skip)]
+    playback_particles: Vec<(Vec3, bool)>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SolverKind {
+    // * `sph` crate on the CPU
+    #[default]
+    Sph,
+    // * Compute shader solver, see gpu_sph.rs
+    Gpu,
+    // * Position based fluids on the CPU, see pbf.rs
+    Pbf,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FluidParams {
+    pub smoothing_length: f32,
+    pub rest_density: f32,
+    pub viscosity: f32,
+    pub stiffness: f32,
+}
+
+impl Default for FluidParams {
+    fn default() -> Self {
+        Self {
+            smoothing_length: 1.0,
+            rest_density: 1.,
+            viscosity: 1.0e-3,
+            stiffness: 5000.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ImportParams {
+    // * A file or a numbered sequence, relative to the scene file
+    pub path: PathBuf,
+    // * File units to simulation units, the renderer scales those by another 10
+    #[serde(default = "default_import_scale")]
+    pub scale: f32,
+}
+
+// * Only used by the PBF solver, which ignores `fluid.stiffness` and `fluid.viscosity`
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+pub struct PbfParams {
+    // * Density constraint iterations per step
+    pub iterations: u32,
+    // * Constraint force mixing, softens the projection where there are few neighbours
+    pub relaxation: f32,
+    // * Tensile instability correction: strength, exponent and the reference distance as a
+    // * fraction of the kernel support
+    pub tensile_strength: f32,
+    pub tensile_power: i32,
+    pub tensile_distance: f32,
+    // * XSPH viscosity
+    pub xsph: f32,
+    // * Vorticity confinement
+    pub vorticity: f32,
+}
+
+impl Default for PbfParams {
+    fn default() -> Self {
+        Self {
+            iterations: 4,
+            relaxation: 1.0e-2,
+            tensile_strength: 1.0e-3,
+            tensile_power: 4,
+            tensile_distance: 0.2,
+            xsph: 0.01,
+            vorticity: 1.0e-3,
+        }
+    }
+}
+
+// * Render space, i.e. simulation units * 10
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct CameraParams {
+    pub pos: [f32; 3],
+    pub dir: [f32; 3],
+}
+
+impl Default for CameraParams {
+    fn default() -> Self {
+        Self {
+            pos: [-78.22161, 135.65047, -27.054756],
+            dir: [0.5124362, -0.8005198, 0.31076893],
+        }
+    }
+}
+
+// * Initial render config, missing values take the defaults of `Config`
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderParams {
+    pub alpha: f32,
+    pub radius: f32,
+    pub max_steps: u32,
+    pub max_distance: f32,
+    // * Water material, see `Shading`
+    pub ior: f32,
+    pub exposure: f32,
+    pub shininess: f32,
+    pub absorption: [f32; 3],
+    pub fluid_color: [f32; 3],
+    // * How particles with `movable = false` are drawn, see `BoundaryMode`
+    pub boundary: BoundaryMode,
+    pub boundary_color: [f32; 3],
+    // * Colouring of the attribute view, the range is fitted to every frame if not given
+    pub attribute: ParticleAttribute,
+    pub colormap: Colormap,
+    pub color_range: Option<[f32; 2]>,
+    pub lights: Vec<LightParams>,
+}
+
+impl Default for RenderParams {
+    fn default() -> Self {
+        let config = Config::default();
+        let shading = config.shading();
+        Self {
+            alpha: config.alpha(),
+            radius: config.radius(),
+            max_steps: config.max_steps(),
+            max_distance: config.max_distance(),
+            ior: shading.ior,
+            exposure: shading.exposure,
+            shininess: shading.shininess,
+            absorption: shading.absorption,
+            fluid_color: shading.fluid_color,
+            boundary: config.boundary(),
+            boundary_color: shading.boundary_color,
+            attribute: config.attribute(),
+            colormap: config.colormap(),
+            color_range: config.color_range(),
+            lights: shading
+                .lights
+                .iter()
+                .map(|light| LightParams {
+                    direction: light.direction,
+                    color: light.color,
+                    intensity: light.intensity,
+                })
+                .collect(),
+        }
+    }
+}
+
+// * `[[render.lights]]`, a directional light in render space
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct LightParams {
+    // * Towards the light
+    pub direction: [f32; 3],
+    #[serde(default = "default_light_color")]
+    pub color: [f32; 3],
+    #[serde(default = "default_light_intensity")]
+    pub intensity: f32,
+}
+
+// * A lattice of particles filling a shape
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Block {
+    pub shape: Shape,
+    pub spacing: f32,
+    #[serde(default = "default_mass")]
+    pub mass: f32,
+    pub movable: bool,
+}
+
+// * `[blocks.shape]`, the kind of shape is given by `type`
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Shape {
+    Box {
+        min: [f32; 3],
+        max: [f32; 3],
+    },
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+    },
+    // * Hollow box, e.g. a container. `open` lists the faces left out ("-x", "+y", ...)
+    Shell {
+        min: [f32; 3],
+        max: [f32; 3],
+        #[serde(default = "default_thickness")]
+        thickness: u32,
+        #[serde(default)]
+        open: Vec<String>,
+    },
+}
+
+fn default_gravity() -> [f32; 3] {
+    [0., 0., -9.81]
+}
+
+fn default_import_scale() -> f32 {
+    1.
+}
+
+fn default_mass() -> f32 {
+    1.
+}
+
+fn default_light_color() -> [f32; 3] {
+    [1.; 3]
+}
+
+fn default_light_intensity() -> f32 {
+    1.
+}
+
+fn default_thickness() -> u32 {
+    1
+}
+
+const FACES: [&str; 6] = ["-x", "+x", "-y", "+y", "-z", "+z"];
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::parse(DEFAULT_SCENE).expect("built-in scene is invalid")
+    }
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read scene {}: {e}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse_in(&source, dir).map_err(|e| format!("invalid scene {}: {e}", path.display()))
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        Self::parse_in(source, Path::new(""))
+    }
+
+    // * Paths in the scene are relative to `dir`
+    fn parse_in(source: &str, dir: &Path) -> Result<Self, String> {
+        let mut scene: Self = toml::from_str(source).map_err(|e| e.to_string())?;
+        scene.validate()?;
+        if let Some(replay) = scene.replay.take() {
+            scene.set_replay(dir.join(replay))?;
+        }
+        if let Some(mut import) = scene.import.take() {
+            import.path = dir.join(import.path);
+            scene.set_import(import)?;
+        }
+        Ok(scene)
+    }
+
+    // * Switches the scene to playing back a recording
+    pub fn set_replay(&mut self, path: PathBuf) -> Result<(), String> {
+        let particles = Recording::open(&path)?.particles()?;
+        self.playback_particles = particles
+            .iter()
+            .map(|p| (p.pos.into(), p.movable))
+            .collect();
+        self.replay = Some(path);
+        self.import = None;
+        Ok(())
+    }
+
+    // * Switches the scene to rendering imported point files
+    pub fn set_import(&mut self, import: ImportParams) -> Result<(), String> {
+        positive("import.scale", import.scale)?;
+        let sequence = PointSequence::open(&import)?;
+        self.playback_particles = sequence.positions().iter().map(|&p| (p, true)).collect();
+        self.import = Some(import);
+        self.replay = None;
+        Ok(())
+    }
+
+    // * True if the particles come from files instead of a solver, they then have a timeline
+    pub fn is_playback(&self) -> bool {
+        self.replay.is_some() || self.import.is_some()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let fluid = &self.fluid;
+        positive("fluid.smoothing_length", fluid.smoothing_length)?;
+        positive("fluid.rest_density", fluid.rest_density)?;
+        positive("fluid.stiffness", fluid.stiffness)?;
+        if fluid.viscosity < 0. || fluid.viscosity.is_nan() {
+            return Err(format!(
+                "fluid.viscosity must not be negative, got {}",
+                fluid.viscosity
+            ));
+        }
+        let pbf = &self.pbf;
+        if pbf.iterations == 0 {
+            return Err("pbf.iterations must be at least 1".into());
+        }
+        positive("pbf.relaxation", pbf.relaxation)?;
+        // * A fraction of the kernel support, the kernel vanishes at the support
+        if !(pbf.tensile_distance > 0. && pbf.tensile_distance < 1.) {
+            return Err(format!(
+                "pbf.tensile_distance must be between 0 and 1, got {}",
+                pbf.tensile_distance
+            ));
+        }
+        if pbf.tensile_power < 1 {
+            return Err(format!(
+                "pbf.tensile_power must be at least 1, got {}",
+                pbf.tensile_power
+            ));
+        }
+        for (name, value) in [
+            ("pbf.tensile_strength", pbf.tensile_strength),
+            ("pbf.xsph", pbf.xsph),
+            ("pbf.vorticity", pbf.vorticity),
+        ] {
+            if value < 0. || value.is_nan() {
+                return Err(format!("{name} must not be negative, got {value}"));
+            }
+        }
+        if self.gravity.iter().any(|g| !g.is_finite()) {
+            return Err(format!("gravity must be finite, got {:?}", self.gravity));
+        }
+        if self.render.alpha >= 0. || self.render.alpha.is_nan() {
+            return Err(format!(
+                "render.alpha must be negative, got {}",
+                self.render.alpha
+            ));
+        }
+        positive("render.radius", self.render.radius)?;
+        if self.render.max_steps == 0 {
+            return Err("render.max_steps must be at least 1".into());
+        }
+        positive("render.max_distance", self.render.max_distance)?;
+        if self.render.ior < 1. || self.render.ior.is_nan() {
+            return Err(format!(
+                "render.ior must be at least 1, got {}",
+                self.render.ior
+            ));
+        }
+        positive("render.exposure", self.render.exposure)?;
+        positive("render.shininess", self.render.shininess)?;
+        for (name, values) in [
+            ("render.absorption", self.render.absorption),
+            ("render.fluid_color", self.render.fluid_color),
+            ("render.boundary_color", self.render.boundary_color),
+        ] {
+            if values.iter().any(|v| *v < 0. || !v.is_finite()) {
+                return Err(format!(
+                    "{name} must be finite and not negative, got {values:?}"
+                ));
+            }
+        }
+        if let Some([min, max]) = self.render.color_range {
+            if !(min.is_finite() && max.is_finite() && min < max) {
+                return Err(format!(
+                    "render.color_range must be two finite increasing values, got [{min}, {max}]"
+                ));
+            }
+        }
+        if self.render.lights.len() > MAX_LIGHTS {
+            return Err(format!(
+                "at most {MAX_LIGHTS} render.lights are supported, got {}",
+                self.render.lights.len()
+            ));
+        }
+        for light in &self.render.lights {
+            let direction = Vec3::from(light.direction);
+            if !direction.is_finite() || direction == Vec3::ZERO {
+                return Err(format!(
+                    "render.lights direction must be a finite non-zero vector, got {direction}"
+                ));
+            }
+            if light.intensity < 0. || light.intensity.is_nan() {
+                return Err(format!(
+                    "render.lights intensity must not be negative, got {}",
+                    light.intensity
+                ));
+            }
+        }
+        let dir = Vec3::from(self.camera.dir);
+        if !dir.is_finite() || dir == Vec3::ZERO {
+            return Err(format!(
+                "camera.dir must be a finite non-zero vector, got {dir}"
+            ));
+        }
+
+        if self.blocks.is_empty() && self.replay.is_none() && self.import.is_none() {
+            return Err("scene has no blocks".into());
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            block
+                .validate()
+                .map_err(|e| format!("block {} ({}): {e}", i + 1, block.shape.name()))?;
+        }
+        Ok(())
+    }
+
+    pub fn particles(&self) -> Vec<Particle> {
+        if self.is_playback() {
+            return self
+                .playback_particles
+                .iter()
+                .map(|&(pos, movable)| Particle::new(pos.into(), 1., movable))
+                .collect();
+        }
+        self.blocks.iter().flat_map(Block::particles).collect()
+    }
+
+    pub fn config(&self) -> Config {
+        let render = &self.render;
+        let mut config = Config::new(
+            render.alpha,
+            render.radius,
+            render.max_steps,
+            render.max_distance,
+        );
+        config.set_shading(Shading {
+            ior: render.ior,
+            exposure: render.exposure,
+            shininess: render.shininess,
+            absorption: render.absorption,
+            fluid_color: render.fluid_color,
+            boundary_color: render.boundary_color,
+            lights: render
+                .lights
+                .iter()
+                .map(|light| Light {
+                    direction: light.direction,
+                    color: light.color,
+                    intensity: light.intensity,
+                })
+                .collect(),
+        });
+        config.set_boundary(render.boundary);
+        config.set_attribute(render.attribute);
+        config.set_colormap(render.colormap);
+        config.set_color_range(render.color_range);
+        config
+    }
+
+    pub fn camera_pose(&self) -> (Vec3, Vec3) {
+        (self.camera.pos.into(), self.camera.dir.into())
+    }
+}
+
+impl Shape {
+    fn name(&self) -> &'static str {
+        match self {
+            Shape::Box { .. } => "box",
+            Shape::Sphere { .. } => "sphere",
+            Shape::Shell { .. } => "shell",
+        }
+    }
+}
+
+impl Block {
+    fn validate(&self) -> Result<(), String> {
+        positive("spacing", self.spacing)?;
+        positive("mass", self.mass)?;
+        match &self.shape {
+            Shape::Box { min, max } => ordered(min, max)?,
+            Shape::Sphere { radius, .. } => positive("radius", *radius)?,
+            Shape::Shell {
+                min,
+                max,
+                thickness,
+                open,
+            } => {
+                ordered(min, max)?;
+                if *thickness == 0 {
+                    return Err("thickness must be at least 1".into());
+                }
+                if let Some(face) = open.iter().find(|f| !FACES.contains(&f.as_str())) {
+                    return Err(format!(
+                        "unknown open face {face:?}, expected one of {FACES:?}"
+                    ));
+                }
+            }
+        }
+        let (min, max) = self.extent();
+        let lattice: f64 = (0..3)
+            .map(|i| ((max[i] - min[i]) as f64 / self.spacing as f64 + 1.0e-4).floor() + 1.)
+            .product();
+        if lattice.is_nan() || lattice > MAX_BLOCK_LATTICE {
+            return Err(format!(
+                "spacing {} gives {lattice:.0} lattice points, at most {MAX_BLOCK_LATTICE} are supported",
+                self.spacing
+            ));
+        }
+        if self.particles().is_empty() {
+            return Err(format!("no particles at spacing {}", self.spacing));
+        }
+        Ok(())
+    }
+
+    // * Box the lattice is laid out in
+    fn extent(&self) -> (Vec3, Vec3) {
+        match &self.shape {
+            Shape::Box { min, max } | Shape::Shell { min, max, .. } => {
+                (Vec3::from(*min), Vec3::from(*max))
+            }
+            Shape::Sphere { center, radius } => {
+                let center = Vec3::from(*center);
+                (center - *radius, center + *radius)
+            }
+        }
+    }
+
+    fn particles(&self) -> Vec<Particle> {
+        let (min, max) = self.extent();
+        // * Tolerance so that a max lying on the lattice is included
+        let counts = ((max - min) / self.spacing + 1.0e-4).floor().as_uvec3() + 1;
+
+        let mut particles = vec![];
+        for z in 0..counts.z {
+            for y in 0..counts.y {
+                for x in 0..counts.x {
+                    let pos = min + self.spacing * Vec3::new(x as f32, y as f32, z as f32);
+                    let inside = match &self.shape {
+                        Shape::Box { .. } => true,
+                        Shape::Sphere { center, radius } => {
+                            pos.distance(Vec3::from(*center)) <= *radius
+                        }
+                        Shape::Shell {
+                            thickness, open, ..
+                        } => {
+                            let index = [x, y, z];
+                            let count = counts.to_array();
+                            let closed = |face: &str| !open.iter().any(|f| f == face);
+                            (0..3).any(|axis| {
+                                let low = index[axis] < *thickness;
+                                let high = index[axis] + *thickness >= count[axis];
+                                (low && closed(FACES[2 * axis]))
+                                    || (high && closed(FACES[2 * axis + 1]))
+                            })
+                        }
+                    };
+                    if inside {
+                        particles.push(Particle::new(pos.into(), self.mass, self.movable));
+                    }
+                }
+            }
+        }
+        particles
+    }
+}
+
+fn positive(name: &str, value: f32) -> Result<(), String> {
+    if value > 0. && value.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("{name} must be positive, got {value}"))
+    }
+}
+
+fn ordered(min: &[f32; 3], max: &[f32; 3]) -> Result<(), String> {
+    if (0..3).all(|i| min[i] <= max[i]) {
+        Ok(())
+    } else {
+        Err(format!("min {min:?} must not exceed max {max:?}"))
+    }
+}
+
+// * Removes `--scene <path>` from the arguments, the remaining ones are left for the other options
+pub fn take_scene_arg(args: &mut Vec<String>) -> Result<Option<PathBuf>, String> {
+    let Some(i) = args.iter().position(|arg| arg == "--scene") else {
+        return Ok(None);
+    };
+    if i + 1 >= args.len() {
+        return Err("missing value for --scene".into());
+    }
+    let path = args.remove(i + 1);
+    args.remove(i);
+    Ok(Some(PathBuf::from(path)))
+}
@@ -0,0 +1,387 @@
+use wgpu::{
+    include_wgsl, util::DeviceExt, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutEntry, Buffer, BufferUsages, Color, ColorTargetState, ColorWrites,
+    CommandEncoder, CompareFunction, DepthStencilState, Device, Extent3d, FragmentState,
+    MultisampleState, PipelineLayoutDescriptor, PrimitiveState, PrimitiveTopology, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderStages, SurfaceConfiguration,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, TextureView,
+    TextureViewDescriptor, VertexBufferLayout, VertexState,
+};
+
+use crate::vertex::{Vertex, SQUARE};
+
+const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+const LINEAR_DEPTH_FORMAT: TextureFormat = TextureFormat::R32Float;
+// * SMOOTHING RADIUS OF THE BILATERAL FILTER IN WORLD UNITS
+const FILTER_WORLD_RADIUS: f32 = 6.;
+const FILTER_DEPTH_FALLOFF: f32 = 4.;
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+struct FilterUniform {
+    direction: [i32; 2],
+    world_radius: f32,
+    depth_falloff: f32,
+}
+
+struct DepthTargets {
+    depth_buffer: TextureView,
+    linear_depth: TextureView,
+    filtered_depth: TextureView,
+}
+
+// * Classic screen space fluid rendering: splat particles as sphere impostors into a depth
+// * texture, smooth it with a separable bilateral filter, rebuild normals and shade.
+pub struct ScreenSpaceRenderer {
+    depth_pipeline: RenderPipeline,
+    filter_pipeline: RenderPipeline,
+    shade_pipeline: RenderPipeline,
+    filter_bind_group_layout: BindGroupLayout,
+    shade_bind_group_layout: BindGroupLayout,
+    filter_buffers: [Buffer; 2],
+    targets: DepthTargets,
+    // * [horizontal: linear -> filtered, vertical: filtered -> linear]
+    filter_bind_groups: [BindGroup; 2],
+    shade_bind_group: BindGroup,
+}
+
+impl ScreenSpaceRenderer {
+    pub fn new(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        camera_bind_group_layout: &BindGroupLayout,
+        config_bind_group_layout: &BindGroupLayout,
+        simulation_bind_group_layout: &BindGroupLayout,
+    ) -> Self {
+        let filter_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Screen Space Filter Bind Group Layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    Self::depth_texture_entry(1),
+                ],
+            });
+        let shade_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Screen Space Shade Bind Group Layout"),
+                entries: &[Self::depth_texture_entry(0)],
+            });
+
+        let filter_buffers = [[1, 0], [0, 1]].map(|direction| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Screen Space Filter Buffer"),
+                contents: bytemuck::bytes_of(&FilterUniform {
+                    direction,
+                    world_radius: FILTER_WORLD_RADIUS,
+                    depth_falloff: FILTER_DEPTH_FALLOFF,
+                }),
+                usage: BufferUsages::UNIFORM,
+            })
+        });
+
+        // * CREATE PIPELINES
+        let depth_pipeline = Self::create_pipeline(
+            device,
+            include_wgsl!("ssf_depth.wgsl"),
+            &[
+                camera_bind_group_layout,
+                config_bind_group_layout,
+                simulation_bind_group_layout,
+            ],
+            &[],
+            LINEAR_DEPTH_FORMAT,
+            true,
+        );
+        let filter_pipeline = Self::create_pipeline(
+            device,
+            include_wgsl!("ssf_filter.wgsl"),
+            &[camera_bind_group_layout, &filter_bind_group_layout],
+            &[Vertex::desc()],
+            LINEAR_DEPTH_FORMAT,
+            false,
+        );
+        let shade_pipeline = Self::create_pipeline(
+            device,
+            include_wgsl!("ssf_shade.wgsl"),
+            &[camera_bind_group_layout, &shade_bind_group_layout],
+            &[Vertex::desc()],
+            config.format,
+            false,
+        );
+
+        let targets = Self::create_targets(device, config.width, config.height);
+        let (filter_bind_groups, shade_bind_group) = Self::create_target_bind_groups(
+            device,
+            &targets,
+            &filter_bind_group_layout,
+            &shade_bind_group_layout,
+            &filter_buffers,
+        );
+
+        Self {
+            depth_pipeline,
+            filter_pipeline,
+            shade_pipeline,
+            filter_bind_group_layout,
+            shade_bind_group_layout,
+            filter_buffers,
+            targets,
+            filter_bind_groups,
+            shade_bind_group,
+        }
+    }
+
+    // * Recreates the intermediate textures, needed whenever the surface size changes
+    pub fn resize(&mut self, device: &Device, width: u32, height: u32) {
+        self.targets = Self::create_targets(device, width, height);
+        (self.filter_bind_groups, self.shade_bind_group) = Self::create_target_bind_groups(
+            device,
+            &self.targets,
+            &self.filter_bind_group_layout,
+            &self.shade_bind_group_layout,
+            &self.filter_buffers,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(
+        &self,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        background: Color,
+        vertex_buffer: &Buffer,
+        camera_bind_group: &BindGroup,
+        config_bind_group: &BindGroup,
+        simulation_bind_group: &BindGroup,
+        num_particles: u32,
+    ) {
+        // * SPLAT PARTICLE DEPTH
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Screen Space Depth Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.targets.linear_depth,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.targets.depth_buffer,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_pipeline(&self.depth_pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, config_bind_group, &[]);
+            render_pass.set_bind_group(2, simulation_bind_group, &[]);
+            render_pass.draw(0..SQUARE.len() as u32, 0..num_particles);
+        }
+        // * BILATERAL FILTER (horizontal, then vertical)
+        for (filter_bind_group, target) in self
+            .filter_bind_groups
+            .iter()
+            .zip([&self.targets.filtered_depth, &self.targets.linear_depth])
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Screen Space Filter Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.filter_pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, filter_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.draw(0..SQUARE.len() as u32, 0..1);
+        }
+        // * SHADE
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Screen Space Shade Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(background),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.shade_pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            render_pass.set_bind_group(1, &self.shade_bind_group, &[]);
+            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+            render_pass.draw(0..SQUARE.len() as u32, 0..1);
+        }
+    }
+
+    fn depth_texture_entry(binding: u32) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }
+
+    fn create_targets(device: &Device, width: u32, height: u32) -> DepthTargets {
+        let texture = |label, format, usage| {
+            device
+                .create_texture(&TextureDescriptor {
+                    label: Some(label),
+                    size: Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                })
+                .create_view(&TextureViewDescriptor::default())
+        };
+        let linear_usage = TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING;
+        DepthTargets {
+            depth_buffer: texture(
+                "Screen Space Depth Buffer",
+                DEPTH_FORMAT,
+                TextureUsages::RENDER_ATTACHMENT,
+            ),
+            linear_depth: texture(
+                "Screen Space Linear Depth",
+                LINEAR_DEPTH_FORMAT,
+                linear_usage,
+            ),
+            filtered_depth: texture(
+                "Screen Space Filtered Depth",
+                LINEAR_DEPTH_FORMAT,
+                linear_usage,
+            ),
+        }
+    }
+
+    fn create_target_bind_groups(
+        device: &Device,
+        targets: &DepthTargets,
+        filter_bind_group_layout: &BindGroupLayout,
+        shade_bind_group_layout: &BindGroupLayout,
+        filter_buffers: &[Buffer; 2],
+    ) -> ([BindGroup; 2], BindGroup) {
+        let filter_bind_group = |buffer: &Buffer, source| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Screen Space Filter Bind Group"),
+                layout: filter_bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                ],
+            })
+        };
+        let filter_bind_groups = [
+            filter_bind_group(&filter_buffers[0], &targets.linear_depth),
+            filter_bind_group(&filter_buffers[1], &targets.filtered_depth),
+        ];
+        let shade_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Screen Space Shade Bind Group"),
+            layout: shade_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&targets.linear_depth),
+            }],
+        });
+        (filter_bind_groups, shade_bind_group)
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        shader: ShaderModuleDescriptor,
+        bind_group_layouts: &[&BindGroupLayout],
+        buffers: &[VertexBufferLayout],
+        format: TextureFormat,
+        depth_test: bool,
+    ) -> RenderPipeline {
+        // * LOAD SHADER
+        let shader = device.create_shader_module(shader);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Screen Space Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Screen Space Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers,
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: depth_test.then_some(DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+    }
+}
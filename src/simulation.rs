@@ -1,78 +1,650 @@
+use std::{
+    io::SeekFrom,
+    iter,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+};
+
 use glam::Vec3;
-use sph::fluid::Fluid;
+use sph::sph::Particle;
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutEntry, Buffer,
     BufferUsages, Device, Queue, ShaderStages,
 };
 
-use crate::config::DEFAULT_DELTA_TIME;
+use crate::{
+    attribute::ParticleAttribute,
+    backend::{self, BackendContext, SimulationBackend},
+    checkpoint::Checkpoint,
+    config::DEFAULT_DELTA_TIME,
+    recording::Recorder,
+    scene::Scene,
+    timestep::DEFAULT_MAX_SUBSTEPS,
+    vtk::{VtkFrame, VtkSeries},
+};
+
+// * Simulation units to render space
+pub const RENDER_SCALE: f32 = 10.;
+
+// * Commands for the solver thread, applied in the order they were sent
+pub enum SimulationCommand {
+    // * Steps from the frame timer, dropped while paused
+    Advance(u32),
+    // * Single step, also taken while paused
+    Step,
+    Pause(bool),
+    Reset(Vec<Particle>),
+    // * Replaces the solver state, the particle count has to match
+    Restore(Checkpoint),
+    // * Answers with the state after all earlier commands
+    Save(Sender<Checkpoint>),
+    // * Answers with the positions in render space after all earlier commands
+    Positions(Sender<Vec<Vec3>>),
+    // * Timeline of a replay, ignored by solvers
+    Seek(SeekFrom),
+    // * Starts streaming the positions of every step to a file, None stops recording
+    Record(Option<PathBuf>),
+    // * Writes the current frame to the VTK series in `dir`, then every `every` steps if given
+    ExportVtk { dir: PathBuf, every: Option<u32> },
+    // * Publishes this attribute with every snapshot, None stops computing it
+    Attribute(Option<ParticleAttribute>),
+}
+
+// * Flags of a particle that never moves, e.g. a wall of the container. A plain float rather
+// * than bits, shaders may flush the subnormal floats small bit patterns turn into to zero.
+pub const PARTICLE_BOUNDARY: f32 = 1.;
+
+// * Flags of a particle, stored in the fourth component of its position in the buffer
+pub fn particle_flags(movable: bool) -> f32 {
+    if movable {
+        0.
+    } else {
+        PARTICLE_BOUNDARY
+    }
+}
+
+pub fn is_boundary(flags: f32) -> bool {
+    flags > 0.5 * PARTICLE_BOUNDARY
+}
+
+// * Smallest box around the positions, None without any
+pub fn particle_bounds(positions: &[Vec3]) -> Option<(Vec3, Vec3)> {
+    (!positions.is_empty()).then(|| {
+        positions.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), p| (min.min(*p), max.max(*p)),
+        )
+    })
+}
+
+// * Positions after a batch of commands, as they are uploaded to the GPU
+struct Snapshot {
+    // * None if the backend wrote them into the simulation buffer and they were not read back
+    positions: Option<Vec<Vec3>>,
+    // * The backend already wrote the positions into the simulation buffer
+    resident: bool,
+    // * Box the positions lie in
+    bounds: Option<(Vec3, Vec3)>,
+    // * Values of the requested attribute
+    scalars: Option<Vec<f32>>,
+    // * Requested attribute the backend turned out not to provide
+    dropped: Option<ParticleAttribute>,
+    // * Simulation time in seconds
+    time: f64,
+    // * Number of commands applied so far
+    sequence: u64,
+}
+
+// * Back buffer of the double buffer: the solver publishes into it, the render loop takes from it
+#[derive(Default)]
+struct SnapshotSlot {
+    snapshot: Mutex<Option<Snapshot>>,
+    published: Condvar,
+}
+
+impl SnapshotSlot {
+    fn publish(&self, snapshot: Snapshot) {
+        *self.snapshot.lock().unwrap() = Some(snapshot);
+        self.published.notify_all();
+    }
+
+    // * Never waits on the solver, None if it holds the lock or nothing new is published
+    fn try_take(&self) -> Option<Snapshot> {
+        self.snapshot.try_lock().ok()?.take()
+    }
+
+    fn wait_for(&self, sequence: u64) -> Snapshot {
+        let mut slot = self.snapshot.lock().unwrap();
+        loop {
+            match slot.take() {
+                Some(snapshot) if snapshot.sequence >= sequence => return snapshot,
+                _ => slot = self.published.wait(slot).unwrap(),
+            }
+        }
+    }
+}
 
 pub struct SimulationState {
-    pub fluid: Fluid,
-    pub buffer: Buffer,
+    pub buffer: Arc<Buffer>,
     pub bind_group: BindGroup,
+    // * Front buffer, the positions currently in `buffer`. None until they are read back from a
+    // * backend that writes the buffer itself.
+    positions: Option<Vec<Vec3>>,
+    bounds: Option<(Vec3, Vec3)>,
+    // * `particle_flags` of every particle, uploaded alongside the positions
+    flags: Vec<f32>,
+    // * Fitted range followed by one value per particle of the requested attribute
+    scalar_buffer: Buffer,
+    attribute: Option<ParticleAttribute>,
+    // * Attributes the backend does not provide, not requested again
+    unavailable: Vec<ParticleAttribute>,
+    // * Range of the attribute over the fluid particles, None until it is first published
+    scalar_range: Option<[f32; 2]>,
+    // * Simulation time of the uploaded positions
+    time: f64,
+    commands: Sender<SimulationCommand>,
+    sent: u64,
+    // * Commands applied in the uploaded snapshot
+    applied: u64,
+    slot: Arc<SnapshotSlot>,
 }
 
 impl SimulationState {
-    pub fn create_simulation(fluid: Fluid, device: &Device) -> (Self, BindGroupLayout) {
-        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+    // * Fails if the particles of a replay or import can no longer be read
+    pub fn create_simulation(
+        scene: &Scene,
+        device: &Arc<Device>,
+        queue: &Arc<Queue>,
+    ) -> Result<(Self, BindGroupLayout), String> {
+        let particles = scene.particles();
+        let initial = Checkpoint::new(&particles, scene.fluid, scene.gravity);
+        let positions = Self::scaled(initial.positions.clone());
+        let flags = Self::flags(&initial.movable);
+        let buffer = Arc::new(device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Simulation Posititons"),
-            contents: bytemuck::cast_slice(&Self::to_raw(
-                &fluid
-                    .particles
-                    .iter()
-                    .map(|p| Vec3::from(p.pos) * 10.)
-                    .collect::<Vec<_>>(),
-            )),
+            contents: bytemuck::cast_slice(&Self::to_raw(&positions, &flags)),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+        }));
+        let scalar_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Simulation Scalars"),
+            contents: bytemuck::cast_slice(&vec![0f32; 2 + positions.len()]),
             usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
         });
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Simulation Bind Group Layout"),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::FRAGMENT,
+            entries: &[0, 1].map(|binding| BindGroupLayoutEntry {
+                binding,
+                visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
                 ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
                     min_binding_size: None,
                 },
                 count: None,
-            }],
+            }),
         });
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: Some("Simulation Bind Group"),
             layout: &bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: buffer.as_entire_binding(),
-            }],
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: scalar_buffer.as_entire_binding(),
+                },
+            ],
         });
-        (
+
+        let backend = backend::create_backend(
+            scene,
+            &BackendContext {
+                device,
+                queue,
+                simulation_bind_group_layout: &bind_group_layout,
+                buffer: &buffer,
+                scalar_buffer: &scalar_buffer,
+            },
+        )?;
+
+        // * SPAWN SOLVER THREAD (exits once the command sender is dropped)
+        let (commands, receiver) = mpsc::channel();
+        let slot = Arc::new(SnapshotSlot::default());
+        let worker_slot = slot.clone();
+        thread::Builder::new()
+            .name("solver".into())
+            .spawn(move || {
+                let solver = Solver {
+                    backend,
+                    state: initial,
+                    recorder: None,
+                    vtk: None,
+                    attribute: None,
+                };
+                Self::solve(solver, receiver, &worker_slot)
+            })
+            .expect("failed to spawn the solver thread");
+
+        Ok((
             Self {
-                fluid,
                 buffer,
                 bind_group,
+                bounds: particle_bounds(&positions),
+                positions: Some(positions),
+                flags,
+                scalar_buffer,
+                attribute: None,
+                unavailable: vec![],
+                scalar_range: None,
+                time: 0.,
+                commands,
+                sent: 0,
+                applied: 0,
+                slot,
             },
             bind_group_layout,
-        )
+        ))
+    }
+    fn solve(mut solver: Solver, commands: Receiver<SimulationCommand>, slot: &SnapshotSlot) {
+        let mut paused = false;
+        let mut sequence = 0;
+        while let Ok(command) = commands.recv() {
+            // * Coalesce everything that queued up while the last batch was solved
+            let mut steps = 0;
+            for command in iter::once(command).chain(commands.try_iter()) {
+                sequence += 1;
+                match command {
+                    SimulationCommand::Advance(n) if !paused => steps += n,
+                    SimulationCommand::Advance(_) => {}
+                    SimulationCommand::Step => steps += 1,
+                    SimulationCommand::Pause(p) => paused = p,
+                    SimulationCommand::Reset(particles) => {
+                        solver.reset(particles);
+                        steps = 0;
+                    }
+                    SimulationCommand::Restore(checkpoint) => {
+                        solver.restore(checkpoint);
+                        steps = 0;
+                    }
+                    SimulationCommand::Save(sender) => {
+                        solver.advance(steps);
+                        steps = 0;
+                        // * Nobody waiting for it any more is not an error of the solver
+                        let _ = sender.send(solver.checkpoint());
+                    }
+                    SimulationCommand::Positions(sender) => {
+                        solver.advance(steps);
+                        steps = 0;
+                        let _ = sender.send(Self::scaled(solver.backend.positions()));
+                    }
+                    SimulationCommand::Seek(to) => {
+                        solver.seek(to);
+                        steps = 0;
+                    }
+                    SimulationCommand::Record(recorder) => {
+                        solver.advance(steps);
+                        steps = 0;
+                        solver.record(recorder);
+                    }
+                    SimulationCommand::ExportVtk { dir, every } => {
+                        solver.advance(steps);
+                        steps = 0;
+                        solver.export_vtk(dir, every);
+                    }
+                    SimulationCommand::Attribute(attribute) => solver.attribute = attribute,
+                }
+            }
+            solver.advance(steps);
+            let requested = solver.attribute;
+            let scalars = solver.scalars();
+            let backend = &solver.backend;
+            let resident = backend.writes_buffer();
+            let bounds = resident
+                .then(|| backend.bounds())
+                .flatten()
+                .map(|(min, max)| (min * RENDER_SCALE, max * RENDER_SCALE));
+            // * Reading positions back from the GPU stalls, only done when the bounds need them
+            let positions = bounds.is_none().then(|| Self::scaled(backend.positions()));
+            slot.publish(Snapshot {
+                bounds: bounds.or_else(|| positions.as_deref().and_then(particle_bounds)),
+                positions,
+                resident,
+                scalars,
+                dropped: requested.filter(|_| solver.attribute.is_none()),
+                time: solver.state.time,
+                sequence,
+            });
+        }
+        solver.record(None);
+    }
+    fn to_raw(positions: &[Vec3], flags: &[f32]) -> Vec<[f32; 4]> {
+        positions
+            .iter()
+            .zip(flags)
+            .map(|(p, &flags)| [p.x, p.y, p.z, flags])
+            .collect()
+    }
+    fn flags(movable: &[bool]) -> Vec<f32> {
+        movable
+            .iter()
+            .map(|&movable| particle_flags(movable))
+            .collect()
+    }
+    fn scaled(mut positions: Vec<Vec3>) -> Vec<Vec3> {
+        positions.iter_mut().for_each(|p| *p *= RENDER_SCALE);
+        positions
     }
-    fn to_raw(positions: &[Vec3]) -> Vec<[f32; 4]> {
-        positions.iter().map(|p| [p.x, p.y, p.z, 0.]).collect()
-    }
-    pub fn update(&mut self, queue: &Queue) {
-        self.fluid.step(DEFAULT_DELTA_TIME);
-        queue.write_buffer(
-            &self.buffer,
-            0,
-            bytemuck::cast_slice(&Self::to_raw(
-                &self
-                    .fluid
-                    .particles
+    // * Particle positions as they are in the buffer. A backend that writes the buffer itself is
+    // * asked for them, which waits for the solver, so this is for occasional use.
+    pub fn positions(&mut self) -> &[Vec3] {
+        if self.positions.is_none() {
+            let (sender, receiver) = mpsc::channel();
+            self.send(SimulationCommand::Positions(sender));
+            match receiver.recv() {
+                Ok(positions) => self.positions = Some(positions),
+                Err(_) => eprintln!("the solver thread stopped"),
+            }
+        }
+        self.positions.as_deref().unwrap_or_default()
+    }
+    // * Positions a surface is built from, leaving out the boundary particles unless `boundary`
+    pub fn surface_positions(&mut self, boundary: bool) -> Vec<Vec3> {
+        self.positions();
+        let positions = self.positions.as_deref().unwrap_or_default();
+        positions
+            .iter()
+            .zip(&self.flags)
+            .filter(|(_, &flags)| boundary || !is_boundary(flags))
+            .map(|(p, _)| *p)
+            .collect()
+    }
+    // * Box the particles lie in, in render space, None without any
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        self.bounds
+    }
+    // * Mean position of the particles that are not boundary, of all if every one is
+    pub fn fluid_centroid(&mut self) -> Option<Vec3> {
+        self.positions();
+        let positions = self.positions.as_deref().unwrap_or_default();
+        let fluid: Vec<Vec3> = positions
+            .iter()
+            .zip(&self.flags)
+            .filter(|(_, &flags)| !is_boundary(flags))
+            .map(|(p, _)| *p)
+            .collect();
+        let positions = if fluid.is_empty() { positions } else { &fluid };
+        (!positions.is_empty()).then(|| positions.iter().sum::<Vec3>() / positions.len() as f32)
+    }
+    pub fn num_particles(&self) -> u32 {
+        self.flags.len() as u32
+    }
+    pub fn send(&mut self, command: SimulationCommand) {
+        self.sent += 1;
+        // * The particle count never changes, but which particles are boundary may
+        match &command {
+            SimulationCommand::Reset(particles) => {
+                self.flags = particles
                     .iter()
-                    .map(|p| Vec3::from(p.pos) * 10.)
-                    .collect::<Vec<_>>(),
-            )),
-        )
+                    .map(|p| particle_flags(p.movable))
+                    .collect()
+            }
+            SimulationCommand::Restore(checkpoint) => self.flags = Self::flags(&checkpoint.movable),
+            _ => (),
+        }
+        // * The solver only stops when this state is dropped
+        self.commands
+            .send(command)
+            .expect("the solver thread panicked");
+    }
+    // * Full solver state after every command sent so far
+    pub fn save_checkpoint(&mut self) -> Result<Checkpoint, String> {
+        let (sender, receiver) = mpsc::channel();
+        self.send(SimulationCommand::Save(sender));
+        receiver
+            .recv()
+            .map_err(|_| "the solver thread stopped".to_string())
+    }
+    pub fn restore_checkpoint(&mut self, checkpoint: Checkpoint) -> Result<(), String> {
+        if checkpoint.num_particles() != self.flags.len() {
+            return Err(format!(
+                "checkpoint has {} particles, the simulation {}",
+                checkpoint.num_particles(),
+                self.flags.len()
+            ));
+        }
+        self.send(SimulationCommand::Restore(checkpoint));
+        Ok(())
+    }
+    // * Asks the solver for the values of `attribute` with every snapshot, if it changed and
+    // * the backend provides it
+    pub fn set_attribute(&mut self, attribute: Option<ParticleAttribute>) {
+        let attribute = attribute.filter(|a| !self.unavailable.contains(a));
+        if attribute != self.attribute {
+            self.attribute = attribute;
+            self.scalar_range = None;
+            self.send(SimulationCommand::Attribute(attribute));
+        }
+    }
+    // * Simulation time in seconds of the positions in the buffer
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+    // * Range of the requested attribute over the fluid particles
+    pub fn scalar_range(&self) -> Option<[f32; 2]> {
+        self.scalar_range
+    }
+    // * Uploads the latest published snapshot if there is one, true if the positions changed
+    pub fn poll(&mut self, queue: &Queue) -> bool {
+        match self.slot.try_take() {
+            Some(snapshot) => {
+                self.upload(snapshot, queue);
+                true
+            }
+            None => false,
+        }
+    }
+    // * Blocks until every command sent so far is applied, e.g. for deterministic offline frames
+    pub fn sync(&mut self, queue: &Queue) {
+        if self.applied >= self.sent {
+            return;
+        }
+        let snapshot = self.slot.wait_for(self.sent);
+        self.upload(snapshot, queue);
+    }
+    fn upload(&mut self, snapshot: Snapshot, queue: &Queue) {
+        self.applied = snapshot.sequence;
+        self.positions = snapshot.positions;
+        self.bounds = snapshot.bounds;
+        self.time = snapshot.time;
+        if let (false, Some(positions)) = (snapshot.resident, &self.positions) {
+            queue.write_buffer(
+                &self.buffer,
+                0,
+                bytemuck::cast_slice(&Self::to_raw(positions, &self.flags)),
+            )
+        }
+        if let Some(scalars) = snapshot.scalars {
+            let range = scalars
+                .iter()
+                .zip(&self.flags)
+                .filter(|(_, &flags)| !is_boundary(flags))
+                .fold([f32::INFINITY, f32::NEG_INFINITY], |[min, max], (&v, _)| {
+                    [min.min(v), max.max(v)]
+                });
+            // * Without any fluid particle the range stays empty, which the shader maps to one end
+            let range = if range[0] <= range[1] {
+                range
+            } else {
+                [0., 0.]
+            };
+            self.scalar_range = Some(range);
+            queue.write_buffer(&self.scalar_buffer, 0, bytemuck::cast_slice(&range));
+            queue.write_buffer(&self.scalar_buffer, 8, bytemuck::cast_slice(&scalars));
+        }
+        // * The values left in the buffer then all map to one end of the colormap
+        if let Some(dropped) = snapshot.dropped {
+            self.unavailable.push(dropped);
+        }
+        if snapshot.dropped.is_some() && snapshot.dropped == self.attribute {
+            self.attribute = None;
+            self.scalar_range = None;
+            queue.write_buffer(&self.scalar_buffer, 0, bytemuck::cast_slice(&[0f32; 2]));
+        }
+    }
+}
+
+// * Everything the solver thread owns
+struct Solver {
+    backend: Box<dyn SimulationBackend>,
+    // * Up to date except for positions and velocities, which only the backend tracks
+    state: Checkpoint,
+    recorder: Option<Recorder>,
+    vtk: Option<VtkSeries>,
+    attribute: Option<ParticleAttribute>,
+}
+
+impl Solver {
+    // * Same rule as the frame timer, a slow solver drops the backlog
+    fn advance(&mut self, steps: u32) {
+        for _ in 0..steps.min(DEFAULT_MAX_SUBSTEPS) {
+            self.backend.step(DEFAULT_DELTA_TIME);
+            self.state.time = self
+                .backend
+                .time()
+                .unwrap_or(self.state.time + DEFAULT_DELTA_TIME as f64);
+            self.write_frame();
+            if self.vtk.as_mut().is_some_and(VtkSeries::step) {
+                self.write_vtk();
+            }
+        }
+    }
+
+    fn reset(&mut self, particles: Vec<Particle>) {
+        if particles.len() != self.backend.num_particles() {
+            eprintln!(
+                "cannot reset {} particles to {}",
+                self.backend.num_particles(),
+                particles.len()
+            );
+            return;
+        }
+        self.state = Checkpoint::new(&particles, self.state.fluid, self.state.gravity);
+        self.backend.reset(particles);
+    }
+
+    fn restore(&mut self, checkpoint: Checkpoint) {
+        if checkpoint.num_particles() != self.backend.num_particles() {
+            eprintln!(
+                "cannot restore {} particles from a checkpoint of {}",
+                self.backend.num_particles(),
+                checkpoint.num_particles()
+            );
+            return;
+        }
+        self.backend.reset(checkpoint.particles());
+        self.state = checkpoint;
+        if let Some(time) = self.backend.time() {
+            self.state.time = time;
+        }
+    }
+
+    fn seek(&mut self, to: SeekFrom) {
+        self.backend.seek(to);
+        if let Some(time) = self.backend.time() {
+            self.state.time = time;
+        }
+    }
+
+    // * Values of the requested attribute, which is dropped if the backend can not provide it
+    fn scalars(&mut self) -> Option<Vec<f32>> {
+        let attribute = self.attribute?;
+        let values = attribute.values(self.backend.as_ref(), self.state.fluid.smoothing_length);
+        if values.is_none() {
+            eprintln!("the solver does not provide {}", attribute.name());
+            self.attribute = None;
+        }
+        values
+    }
+
+    fn checkpoint(&mut self) -> Checkpoint {
+        self.state.positions = self.backend.positions();
+        self.state.velocities = self
+            .backend
+            .velocities()
+            .unwrap_or_else(|| vec![Vec3::ZERO; self.backend.num_particles()]);
+        self.state.clone()
+    }
+
+    // * Closes the current recording, a new one starts with the current positions
+    fn record(&mut self, path: Option<PathBuf>) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(frames) => println!("recorded {frames} frames"),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        let Some(path) = path else {
+            return;
+        };
+        match Recorder::create(&path, &self.state.movable, DEFAULT_DELTA_TIME) {
+            Ok(recorder) => self.recorder = Some(recorder),
+            Err(e) => eprintln!("{e}"),
+        }
+        self.write_frame();
+    }
+
+    fn write_frame(&mut self) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.write_frame(&self.backend.positions()) {
+            eprintln!("{e}, recording stopped");
+            self.recorder = None;
+        }
+    }
+
+    // * Continues the series if it goes to the same directory, starts a new one otherwise
+    fn export_vtk(&mut self, dir: PathBuf, every: Option<u32>) {
+        match &mut self.vtk {
+            Some(series) if series.dir() == dir => series.set_every(every),
+            _ => match VtkSeries::new(&dir, every) {
+                Ok(series) => self.vtk = Some(series),
+                Err(e) => {
+                    eprintln!("{e}");
+                    return;
+                }
+            },
+        }
+        self.write_vtk();
+    }
+
+    fn write_vtk(&mut self) {
+        let Some(series) = &mut self.vtk else {
+            return;
+        };
+        let backend = &self.backend;
+        let positions = backend.positions();
+        let velocities = backend.velocities();
+        let densities = backend.densities();
+        let pressures = backend.pressures();
+        let frame = VtkFrame {
+            time: self.state.time,
+            positions: &positions,
+            velocities: velocities.as_deref(),
+            densities: densities.as_deref(),
+            pressures: pressures.as_deref(),
+            movable: &self.state.movable,
+        };
+        match series.write(&frame) {
+            Ok(path) => println!("exported {}", path.display()),
+            Err(e) => eprintln!("{e}"),
+        }
     }
 }
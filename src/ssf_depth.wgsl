@@ -0,0 +1,94 @@
+// * Screen space fluid, pass 1: splats every particle as a sphere impostor and
+// * writes the linear view depth of the closest sphere surface.
+// * Boundary particles are left out unless the boundary mode is material.
+const NEAR: f32 = 0.1;
+const FAR: f32 = 1000.;
+
+// * Must match `BoundaryMode` in config.rs
+const BOUNDARY_MATERIAL = 3u;
+// * Same as PARTICLE_BOUNDARY in simulation.rs, stored in the w of every position
+const PARTICLE_BOUNDARY = 1.;
+
+// * +X = Right; +Y = Up; +Z = Back
+struct Camera {
+    pos: vec3<f32>,
+    screen_width: f32,
+    dir: vec3<f32>,
+    screen_height: f32,
+    up: vec3<f32>,
+    screen_dist: f32,
+    view_matrix: mat4x4<f32>,
+    inverse_view_matrix: mat4x4<f32>
+}
+
+struct Config {
+    alpha: f32,
+    radius: f32,
+    max_steps: u32,
+    max_distance: f32,
+    view: u32,
+    slice_depth: f32,
+    ior: f32,
+    exposure: f32,
+    absorption: vec3<f32>,
+    shininess: f32,
+    fluid_color: vec3<f32>,
+    num_lights: u32,
+    boundary_color: vec3<f32>,
+    boundary: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) center: vec3<f32>,
+};
+
+struct FragmentOutput {
+    @builtin(frag_depth) frag_depth: f32,
+    @location(0) depth: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var<uniform> config: Config;
+
+@group(2) @binding(0)
+var<storage> positions: array<vec4<f32>>;
+
+// * Perspective projection matching the ray directions of the ray marcher
+fn project(view_pos: vec3<f32>) -> vec4<f32> {
+    let fx = camera.screen_dist / (camera.screen_width * 0.5);
+    let fy = camera.screen_dist / (camera.screen_height * 0.5);
+    let z = (view_pos.z * FAR + NEAR * FAR) / (NEAR - FAR);
+    return vec4<f32>(view_pos.x * fx, view_pos.y * fy, z, -view_pos.z);
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> VertexOutput {
+    let uv = vec2<f32>(f32(vertex_index & 1u), f32(vertex_index >> 1u)) * 2. - 1.;
+    let particle = positions[instance_index];
+    if config.boundary != BOUNDARY_MATERIAL && particle.w > 0.5 * PARTICLE_BOUNDARY {
+        // * Every corner outside the clip volume, nothing is rasterised
+        return VertexOutput(vec4<f32>(2., 2., 2., 1.), uv, vec3<f32>(0.));
+    }
+    let center = (camera.view_matrix * vec4<f32>(particle.xyz, 1.)).xyz;
+    let view_pos = center + vec3<f32>(uv * config.radius, 0.);
+    return VertexOutput(project(view_pos), uv, center);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let r2 = dot(in.uv, in.uv);
+    if r2 > 1. {
+        discard;
+    }
+    let normal = vec3<f32>(in.uv, sqrt(1. - r2));
+    let clip = project(in.center + normal * config.radius);
+    return FragmentOutput(clip.z / clip.w, clip.w);
+}
@@ -0,0 +1,74 @@
+// * Screen space fluid, pass 2: one direction of a separable bilateral filter
+// * over the linear depth. A depth of 0 means no fluid was splatted there.
+const MAX_FILTER_RADIUS: i32 = 16;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+struct Filter {
+    direction: vec2<i32>,
+    // * Filter radius in world units, converted to pixels per fragment
+    world_radius: f32,
+    // * Depth differences larger than this are treated as edges
+    depth_falloff: f32,
+}
+
+// * +X = Right; +Y = Up; +Z = Back
+struct Camera {
+    pos: vec3<f32>,
+    screen_width: f32,
+    dir: vec3<f32>,
+    screen_height: f32,
+    up: vec3<f32>,
+    screen_dist: f32,
+    view_matrix: mat4x4<f32>,
+    inverse_view_matrix: mat4x4<f32>
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var<uniform> filter_params: Filter;
+@group(1) @binding(1)
+var depth: texture_2d<f32>;
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    return VertexOutput(vec4<f32>(model.position, 1.));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) f32 {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let center = textureLoad(depth, pixel, 0).r;
+    if center <= 0. {
+        return 0.;
+    }
+    let size = vec2<i32>(textureDimensions(depth));
+    let radius = min(i32(filter_params.world_radius * camera.screen_dist / center), MAX_FILTER_RADIUS);
+    let sigma = max(f32(radius) * 0.5, 1.);
+
+    var sum = 0.;
+    var weight_sum = 0.;
+    for (var i = -radius; i <= radius; i++) {
+        let sample_pixel = clamp(pixel + filter_params.direction * i, vec2<i32>(0), size - 1);
+        let sample = textureLoad(depth, sample_pixel, 0).r;
+        if sample <= 0. {
+            continue;
+        }
+        let spatial = f32(i) / sigma;
+        let range = (sample - center) / filter_params.depth_falloff;
+        let weight = exp(-0.5 * (spatial * spatial + range * range));
+        sum += sample * weight;
+        weight_sum += weight;
+    }
+    return sum / weight_sum;
+}
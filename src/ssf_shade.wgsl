@@ -0,0 +1,92 @@
+// * Screen space fluid, pass 3: rebuilds view space positions and normals from
+// * the smoothed depth and shades the fluid surface.
+const LIGHT_DIR = vec3<f32>(0.3, 1., 0.5);
+const FLUID_COLOR = vec3<f32>(0.1, 0.4, 0.8);
+const SPECULAR_POWER: f32 = 64.;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+// * +X = Right; +Y = Up; +Z = Back
+struct Camera {
+    pos: vec3<f32>,
+    screen_width: f32,
+    dir: vec3<f32>,
+    screen_height: f32,
+    up: vec3<f32>,
+    screen_dist: f32,
+    view_matrix: mat4x4<f32>,
+    inverse_view_matrix: mat4x4<f32>
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var depth: texture_2d<f32>;
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    return VertexOutput(vec4<f32>(model.position, 1.));
+}
+
+fn load_depth(pixel: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(depth));
+    return textureLoad(depth, clamp(pixel, vec2<i32>(0), size - 1), 0).r;
+}
+
+fn view_pos(pixel: vec2<i32>, linear_depth: f32) -> vec3<f32> {
+    let p = vec2<f32>(pixel) + 0.5;
+    return vec3<f32>(
+        (p.x - camera.screen_width * 0.5) / camera.screen_dist,
+        (-p.y + camera.screen_height * 0.5) / camera.screen_dist,
+        -1.
+    ) * linear_depth;
+}
+
+// * Difference towards the neighbour along `offset`, taking the smaller side to avoid smearing over edges
+fn position_delta(pixel: vec2<i32>, offset: vec2<i32>, center: vec3<f32>) -> vec3<f32> {
+    let forward_depth = load_depth(pixel + offset);
+    let backward_depth = load_depth(pixel - offset);
+    let forward = view_pos(pixel + offset, forward_depth) - center;
+    let backward = center - view_pos(pixel - offset, backward_depth);
+    if forward_depth <= 0. {
+        return backward;
+    }
+    if backward_depth <= 0. || abs(forward.z) < abs(backward.z) {
+        return forward;
+    }
+    return backward;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let linear_depth = load_depth(pixel);
+    if linear_depth <= 0. {
+        discard;
+    }
+    let pos = view_pos(pixel, linear_depth);
+    // * Texture rows go down, so step to the previous row for view space up
+    let dx = position_delta(pixel, vec2<i32>(1, 0), pos);
+    let dy = position_delta(pixel, vec2<i32>(0, -1), pos);
+    let normal = normalize(cross(dx, dy));
+
+    let light = normalize((camera.view_matrix * vec4<f32>(normalize(LIGHT_DIR), 0.)).xyz);
+    let view = normalize(-pos);
+    let half_dir = normalize(light + view);
+
+    let diffuse = max(dot(normal, light), 0.) * 0.8 + 0.2;
+    let specular = pow(max(dot(normal, half_dir), 0.), SPECULAR_POWER);
+    let fresnel = pow(1. - max(dot(normal, view), 0.), 5.);
+
+    let color = FLUID_COLOR * diffuse + vec3<f32>(specular) + fresnel * 0.3;
+    return vec4<f32>(color, 1.);
+}
@@ -1,10 +1,24 @@
-use std::time::Instant;
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
-use winit::event_loop::EventLoop;
+use wgpu::SurfaceError;
+use winit::{dpi::PhysicalSize, event_loop::EventLoop};
 
 use crate::{
-    camera::Camera, config::Config, env::Environment, input, render::RenderState,
-    simulation::SimulationState,
+    camera::{Camera, CameraMode},
+    camera_path::{CameraPath, Keyframe},
+    checkpoint::Checkpoint,
+    config::Config,
+    env::Environment,
+    grid::GridState,
+    input,
+    render::{RenderMode, RenderState},
+    scene::{FluidParams, Scene},
+    simulation::{SimulationCommand, SimulationState},
+    timestep::FixedTimestep,
+    ui::{Overlay, Status, UiAction},
 };
 use winit::{
     event::{ElementState, Event, VirtualKeyCode, WindowEvent},
@@ -14,10 +28,21 @@ pub struct State {
     pub env: Environment,
     pub render_state: RenderState,
     pub simulation_state: SimulationState,
+    pub grid: GridState,
     pub camera: Camera,
     pub config: Config,
     pub delta_time: f32,
     pub paused: bool,
+    pub scene: Scene,
+    // * File the scene was loaded from, None for the built-in one
+    pub scene_path: Option<PathBuf>,
+    pub timestep: FixedTimestep,
+    pub recording: bool,
+    // * Keyframes recorded or loaded so far
+    pub camera_path: CameraPath,
+    // * Flies the camera along `camera_path` instead of the controller
+    pub playing_path: bool,
+    pub overlay: Overlay,
 }
 
 impl State {
@@ -41,30 +66,99 @@ impl State {
                         ..
                     } => *control_flow = ControlFlow::Exit,
 
+                    WindowEvent::Resized(size) => self.resize(*size),
+                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        self.overlay.on_event(event);
+                        self.resize(**new_inner_size)
+                    }
+
+                    // * THE OVERLAY ONLY GETS INPUT WHILE THE CURSOR IS FREE
                     event => {
-                        input::input(&mut self, event);
+                        if self.env.cursor_grab || !self.overlay.on_event(event) {
+                            input::input(&mut self, event);
+                        }
                     }
                 },
                 Event::MainEventsCleared => {
                     // * UPDATE SIMULATION
                     if !self.paused {
-                        self.update_simulation()
+                        let substeps = self.timestep.advance(self.delta_time);
+                        if substeps > 0 {
+                            self.simulation_state
+                                .send(SimulationCommand::Advance(substeps));
+                        }
                     }
-                    // * UPDATE CAMERA
-                    if self.env.cursor_grab {
+                    self.update_simulation();
+                    // * UPDATE CAMERA (ORBITING NEEDS THE CURSOR FOR DRAGGING)
+                    let pose = self
+                        .playing_path
+                        .then(|| self.camera_path.sample(self.simulation_state.time()))
+                        .flatten();
+                    if let Some(pose) = pose {
+                        self.camera.set_pose(pose.pos, pose.dir, &self.env.queue);
+                        self.camera.set_fov(pose.fov, &self.env.queue);
+                    } else if self.env.cursor_grab
+                        || self.camera.controller.mode == CameraMode::Orbit
+                    {
                         self.camera.update(self.delta_time, &self.env.queue);
                     }
+                    // * NOTHING TO DRAW WHILE MINIMISED
+                    if self.env.is_minimized() {
+                        return;
+                    }
+                    // * EXTRACT MESH
+                    if self.render_state.mode == RenderMode::Mesh && self.render_state.mesh.dirty {
+                        self.render_state.mesh.extract(
+                            &self.env.device,
+                            &self.env.queue,
+                            &mut self.simulation_state,
+                            &self.grid,
+                            &self.config,
+                        );
+                    }
+                    // * BUILD THE OVERLAY
+                    let status = Status {
+                        config: self.config.clone(),
+                        fluid: (!self.scene.is_playback()).then_some(self.scene.fluid),
+                        paused: self.paused,
+                        num_particles: self.simulation_state.num_particles(),
+                        camera_pos: self.camera.entity.pos,
+                        camera_dir: self.camera.entity.dir,
+                        camera_mode: self.camera.controller.mode,
+                        camera_target: self.camera.controller.target,
+                        camera_fov: self.camera.fov_y,
+                        keyframes: self.camera_path.keyframes.len(),
+                        playing_path: self.playing_path,
+                        scalar_range: self.simulation_state.scalar_range(),
+                    };
+                    for action in self.overlay.update(&self.env.window, &status) {
+                        self.apply(action);
+                    }
                     // * RENDER
-                    self.render_state.render_call(
+                    match self.render_state.render_call(
                         &self.simulation_state.bind_group,
                         &self.env,
                         &self.camera.bind_group,
-                    );
+                        &self.grid.bind_group,
+                        self.simulation_state.num_particles(),
+                        &mut self.overlay,
+                    ) {
+                        Ok(()) => {}
+                        // * RECONFIGURE THE SURFACE AND TRY AGAIN NEXT FRAME
+                        Err(SurfaceError::Lost | SurfaceError::Outdated) => {
+                            self.resize(self.env.window.inner_size())
+                        }
+                        Err(SurfaceError::Timeout) => eprintln!("surface timeout, frame skipped"),
+                        Err(SurfaceError::OutOfMemory) => {
+                            eprintln!("out of memory, exiting");
+                            *control_flow = ControlFlow::Exit;
+                        }
+                    }
                 }
                 Event::RedrawEventsCleared => {
                     // * UPDATE DELTA TIME
                     self.delta_time = start.elapsed().as_secs_f32();
-                    println!("{}", 1. / self.delta_time);
+                    self.overlay.push_frame_time(self.delta_time);
                     start = Instant::now();
                 }
 
@@ -79,7 +173,197 @@ impl State {
         })
     }
 
+    pub fn resize(&mut self, size: PhysicalSize<u32>) {
+        if self.env.resize(size) {
+            self.camera.resize(size.width, size.height, &self.env.queue);
+            self.render_state
+                .resize(&self.env.device, size.width, size.height);
+        }
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        // * Also drops steps that are still queued for the solver
+        self.simulation_state.send(SimulationCommand::Pause(paused));
+    }
+
+    // * Restarts the current scene from its initial particles
+    pub fn reset(&mut self) {
+        self.simulation_state
+            .send(SimulationCommand::Reset(self.scene.particles()));
+        self.timestep.reset();
+    }
+
+    // * Applies a render config to the ray marcher, the grid and the mesh extraction
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.render_state.set_config(&self.env.queue, &self.config);
+        self.simulation_state
+            .set_attribute(self.config.shown_attribute());
+        // * The influence range and with it the grid cutoff depend on the config
+        self.grid.rebuild(
+            &self.env.device,
+            &self.env.queue,
+            &self.simulation_state,
+            &self.config,
+        );
+        self.render_state.mesh.dirty = true;
+    }
+
+    // * Restarts the solver with other fluid parameters, keeping the view and render config
+    pub fn set_fluid(&mut self, fluid: FluidParams) -> Result<(), String> {
+        let mut scene = self.scene.clone();
+        scene.fluid = fluid;
+        self.restart_scene(scene)
+    }
+
+    // * Loads a variant of the current scene, keeping the view and render config
+    fn restart_scene(&mut self, scene: Scene) -> Result<(), String> {
+        let (pos, dir, config) = (
+            self.camera.entity.pos,
+            self.camera.entity.dir,
+            self.config.clone(),
+        );
+        self.load_scene(scene)?;
+        self.camera.set_pose(pos, dir, &self.env.queue);
+        self.set_config(config);
+        Ok(())
+    }
+
+    // * Carries out a change made in the overlay
+    fn apply(&mut self, action: UiAction) {
+        match action {
+            UiAction::SetConfig(config) => self.set_config(config),
+            UiAction::SetFluid(fluid) => {
+                if let Err(e) = self.set_fluid(fluid) {
+                    eprintln!("{e}");
+                }
+            }
+            UiAction::SetPaused(paused) => self.set_paused(paused),
+            UiAction::Step => self.simulation_state.send(SimulationCommand::Step),
+            UiAction::Reset => self.reset(),
+            UiAction::SetCameraMode(mode) => self.set_camera_mode(mode),
+            UiAction::SetFov(fov) => self.camera.set_fov(fov, &self.env.queue),
+            UiAction::AddKeyframe => self.add_keyframe(),
+            UiAction::SetPlayingPath(playing) => self.playing_path = playing,
+        }
+    }
+
+    // * Keyframes the current camera at the current simulation time, replacing one at that time
+    pub fn add_keyframe(&mut self) {
+        let keyframe = Keyframe {
+            time: self.simulation_state.time(),
+            pos: self.camera.entity.pos.into(),
+            dir: self.camera.entity.dir.into(),
+            fov: self.camera.fov_y,
+            easing: Default::default(),
+        };
+        println!(
+            "keyframe {} at {:.3}s: pos {:?} dir {:?} fov {}",
+            self.camera_path.keyframes.len() + 1,
+            keyframe.time,
+            keyframe.pos,
+            keyframe.dir,
+            keyframe.fov
+        );
+        self.camera_path.add(keyframe);
+    }
+
+    // * Orbiting starts around the centre of the fluid, or a point in front without particles
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        let entity = &self.camera.entity;
+        let target = self
+            .simulation_state
+            .fluid_centroid()
+            .unwrap_or(entity.pos + entity.dir * 100.);
+        self.camera.set_mode(mode, target, &self.env.queue);
+    }
+
+    // * Restarts with another scene, which may use a different solver and particle count.
+    // * Window and render pipelines are kept, as is the running simulation if this fails.
+    pub fn load_scene(&mut self, scene: Scene) -> Result<(), String> {
+        let (simulation_state, simulation_bind_group_layout) =
+            SimulationState::create_simulation(&scene, &self.env.device, &self.env.queue)?;
+        let (mut grid, _) = GridState::create_grid(
+            &self.env.device,
+            &simulation_bind_group_layout,
+            simulation_state.num_particles(),
+        );
+        grid.set_enabled(self.grid.enabled(), &self.env.queue);
+        grid.rebuild(
+            &self.env.device,
+            &self.env.queue,
+            &simulation_state,
+            &self.config,
+        );
+        // * Dropping the old state also stops its solver thread
+        self.simulation_state = simulation_state;
+        self.recording = false;
+        self.grid = grid;
+        self.simulation_state
+            .send(SimulationCommand::Pause(self.paused));
+        self.timestep.reset();
+
+        let (pos, dir) = scene.camera_pose();
+        self.camera.set_pose(pos, dir, &self.env.queue);
+        self.set_config(scene.config());
+        self.scene = scene;
+        Ok(())
+    }
+
+    pub fn save_checkpoint(&mut self, path: &Path) -> Result<(), String> {
+        self.simulation_state.save_checkpoint()?.save(path)
+    }
+
+    // * Resumes from a saved state of this scene. Different fluid parameters or gravity in the
+    // * checkpoint restart the solver with them.
+    pub fn load_checkpoint(&mut self, path: &Path) -> Result<(), String> {
+        let checkpoint = Checkpoint::load(path)?;
+        let num_particles = self.simulation_state.num_particles() as usize;
+        if checkpoint.num_particles() != num_particles {
+            return Err(format!(
+                "checkpoint {} has {} particles, the scene {num_particles}",
+                path.display(),
+                checkpoint.num_particles()
+            ));
+        }
+        if checkpoint.fluid != self.scene.fluid || checkpoint.gravity != self.scene.gravity {
+            let mut scene = self.scene.clone();
+            scene.fluid = checkpoint.fluid;
+            scene.gravity = checkpoint.gravity;
+            self.restart_scene(scene)?;
+        }
+        self.simulation_state.restore_checkpoint(checkpoint)?;
+        self.timestep.reset();
+        Ok(())
+    }
+
+    // * Streams every following step to `path`, None closes the file
+    pub fn record(&mut self, path: Option<PathBuf>) {
+        self.recording = path.is_some();
+        self.simulation_state.send(SimulationCommand::Record(path));
+    }
+
+    // * Reads the scene file again, e.g. after editing it
+    pub fn reload_scene(&mut self) -> Result<(), String> {
+        let scene = match &self.scene_path {
+            Some(path) => Scene::load(path)?,
+            None => Scene::default(),
+        };
+        self.load_scene(scene)
+    }
+
+    // * Picks up the latest positions from the solver thread without waiting for it
     pub fn update_simulation(&mut self) {
-        self.simulation_state.update(&self.env.queue);
+        if !self.simulation_state.poll(&self.env.queue) {
+            return;
+        }
+        self.grid.rebuild(
+            &self.env.device,
+            &self.env.queue,
+            &self.simulation_state,
+            &self.config,
+        );
+        self.render_state.mesh.dirty = true;
     }
 }
@@ -0,0 +1,59 @@
+use crate::config::DEFAULT_DELTA_TIME;
+
+// * At most this many solver steps per frame, slower frames let the simulation fall behind
+pub const DEFAULT_MAX_SUBSTEPS: u32 = 8;
+const MIN_TIME_SCALE: f32 = 1. / 64.;
+const MAX_TIME_SCALE: f32 = 64.;
+
+// * Accumulates wall-clock time and converts it into a number of fixed size solver steps
+pub struct FixedTimestep {
+    step: f32,
+    accumulator: f32,
+    max_substeps: u32,
+    time_scale: f32,
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(DEFAULT_DELTA_TIME, DEFAULT_MAX_SUBSTEPS)
+    }
+}
+
+impl FixedTimestep {
+    pub fn new(step: f32, max_substeps: u32) -> Self {
+        Self {
+            step,
+            accumulator: 0.,
+            max_substeps,
+            time_scale: 1.,
+        }
+    }
+
+    // * Number of steps to take for a frame that took `frame_time` seconds
+    pub fn advance(&mut self, frame_time: f32) -> u32 {
+        self.accumulator += frame_time * self.time_scale;
+        let substeps = (self.accumulator / self.step) as u32;
+        if substeps > self.max_substeps {
+            // * Drop the backlog instead of trying to catch up (spiral of death)
+            self.accumulator = 0.;
+            self.max_substeps
+        } else {
+            self.accumulator -= substeps as f32 * self.step;
+            substeps
+        }
+    }
+
+    // * Forgets time that was not yet turned into steps, e.g. after a reset
+    pub fn reset(&mut self) {
+        self.accumulator = 0.;
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    // * 1 is real time, below slows down, above fast-forwards (bounded by the substep cap)
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::VecDeque;
+
+use egui::{ClippedPrimitive, Context, DragValue, TexturesDelta};
+use egui_plot::{Line, Plot, PlotPoints};
+use egui_wgpu::renderer::{Renderer, ScreenDescriptor};
+use glam::Vec3;
+use wgpu::{CommandEncoder, Device, Queue, TextureFormat, TextureView};
+use winit::{event::WindowEvent, event_loop::EventLoop, window::Window};
+
+use crate::{
+    attribute::ParticleAttribute,
+    camera::CameraMode,
+    config::{BoundaryMode, Colormap, Config, ViewMode},
+    scene::FluidParams,
+};
+
+// * Frame times kept for the graph
+const FRAME_HISTORY: usize = 240;
+
+// * What the overlay shows, copied from the state every frame
+pub struct Status {
+    pub config: Config,
+    // * None while playing back, there is no solver to tune
+    pub fluid: Option<FluidParams>,
+    pub paused: bool,
+    pub num_particles: u32,
+    pub camera_pos: Vec3,
+    pub camera_dir: Vec3,
+    pub camera_mode: CameraMode,
+    pub camera_target: Vec3,
+    pub camera_fov: f32,
+    pub keyframes: usize,
+    pub playing_path: bool,
+    // * Range of the coloured attribute over the fluid in the current frame
+    pub scalar_range: Option<[f32; 2]>,
+}
+
+// * Changes requested through the overlay, applied by the state after the frame was built
+pub enum UiAction {
+    SetConfig(Config),
+    // * Restarts the solver with the new parameters
+    SetFluid(FluidParams),
+    SetPaused(bool),
+    Step,
+    Reset,
+    // * Orbiting starts around the centre of the fluid
+    SetCameraMode(CameraMode),
+    SetFov(f32),
+    // * Keyframe of the current camera at the current simulation time
+    AddKeyframe,
+    SetPlayingPath(bool),
+}
+
+struct UiFrame {
+    primitives: Vec<ClippedPrimitive>,
+    textures: TexturesDelta,
+    pixels_per_point: f32,
+}
+
+// * egui overlay with controls for the simulation and the renderer, drawn on top of the frame
+pub struct Overlay {
+    pub visible: bool,
+    context: Context,
+    input: egui_winit::State,
+    renderer: Renderer,
+    // * Tessellated by `update`, drawn by `paint`
+    frame: Option<UiFrame>,
+    panels: Panels,
+}
+
+impl Overlay {
+    pub fn new(
+        event_loop: &EventLoop<()>,
+        window: &Window,
+        device: &Device,
+        format: TextureFormat,
+    ) -> Self {
+        let mut input = egui_winit::State::new(event_loop);
+        input.set_pixels_per_point(window.scale_factor() as f32);
+        input.set_max_texture_side(device.limits().max_texture_dimension_2d as usize);
+        Self {
+            visible: true,
+            context: Context::default(),
+            input,
+            renderer: Renderer::new(device, format, None, 1),
+            frame: None,
+            panels: Panels::default(),
+        }
+    }
+
+    // * True if the overlay used the event, e.g. a click on one of its windows
+    pub fn on_event(&mut self, event: &WindowEvent) -> bool {
+        self.visible && self.input.on_event(&self.context, event).consumed
+    }
+
+    pub fn push_frame_time(&mut self, frame_time: f32) {
+        self.panels.push_frame_time(frame_time);
+    }
+
+    // * Lays out the overlay for this frame, returns what the user changed
+    pub fn update(&mut self, window: &Window, status: &Status) -> Vec<UiAction> {
+        if !self.visible {
+            return vec![];
+        }
+        let raw_input = self.input.take_egui_input(window);
+        let mut actions = vec![];
+        let output = self.context.run(raw_input, |context| {
+            actions = self.panels.show(context, status);
+        });
+        self.input
+            .handle_platform_output(window, &self.context, output.platform_output);
+        self.frame = Some(UiFrame {
+            primitives: self.context.tessellate(output.shapes),
+            textures: output.textures_delta,
+            pixels_per_point: self.context.pixels_per_point(),
+        });
+        actions
+    }
+
+    // * Draws the last laid out frame on top of `view`
+    pub fn paint(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        size: [u32; 2],
+    ) {
+        let Some(frame) = self.frame.take() else {
+            return;
+        };
+        for (id, delta) in &frame.textures.set {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        let screen = ScreenDescriptor {
+            size_in_pixels: size,
+            pixels_per_point: frame.pixels_per_point,
+        };
+        // * Only needed for paint callbacks, which the overlay does not use
+        let callback_buffers =
+            self.renderer
+                .update_buffers(device, queue, encoder, &frame.primitives, &screen);
+        queue.submit(callback_buffers);
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("UI Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            self.renderer
+                .render(&mut render_pass, &frame.primitives, &screen);
+        }
+        for id in &frame.textures.free {
+            self.renderer.free_texture(id);
+        }
+    }
+}
+
+// * Contents of the overlay and the values being edited in it
+#[derive(Default)]
+struct Panels {
+    frame_times: VecDeque<f32>,
+    // * Fluid parameters being edited and those of the running solver
+    fluid: Option<FluidParams>,
+    applied_fluid: Option<FluidParams>,
+}
+
+impl Panels {
+    fn push_frame_time(&mut self, frame_time: f32) {
+        if self.frame_times.len() == FRAME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    fn show(&mut self, context: &Context, status: &Status) -> Vec<UiAction> {
+        // * Start editing from the solver's values whenever they change, e.g. after a reload
+        if status.fluid != self.applied_fluid {
+            self.fluid = status.fluid;
+            self.applied_fluid = status.fluid;
+        }
+        let mut actions = vec![];
+        egui::Window::new("Controls")
+            .default_pos([10., 10.])
+            .show(context, |ui| {
+                Self::simulation_panel(ui, status, &mut actions);
+                self.fluid_panel(ui, &mut actions);
+                Self::render_panel(ui, status, &mut actions);
+                self.performance_panel(ui);
+                Self::camera_panel(ui, status, &mut actions);
+            });
+        actions
+    }
+
+    fn simulation_panel(ui: &mut egui::Ui, status: &Status, actions: &mut Vec<UiAction>) {
+        egui::CollapsingHeader::new("Simulation")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.label(format!("particles: {}", status.num_particles));
+                ui.horizontal(|ui| {
+                    let pause = if status.paused { "Run" } else { "Pause" };
+                    if ui.button(pause).clicked() {
+                        actions.push(UiAction::SetPaused(!status.paused));
+                    }
+                    if ui
+                        .add_enabled(status.paused, egui::Button::new("Step"))
+                        .clicked()
+                    {
+                        actions.push(UiAction::Step);
+                    }
+                    if ui.button("Reset").clicked() {
+                        actions.push(UiAction::Reset);
+                    }
+                });
+            });
+    }
+
+    fn fluid_panel(&mut self, ui: &mut egui::Ui, actions: &mut Vec<UiAction>) {
+        let (Some(fluid), Some(applied)) = (&mut self.fluid, self.applied_fluid) else {
+            return;
+        };
+        egui::CollapsingHeader::new("Fluid").show(ui, |ui| {
+            egui::Grid::new("fluid").num_columns(2).show(ui, |ui| {
+                ui.label("smoothing length");
+                ui.add(
+                    DragValue::new(&mut fluid.smoothing_length)
+                        .speed(0.01)
+                        .clamp_range(0.01..=f32::MAX),
+                );
+                ui.end_row();
+                ui.label("rest density");
+                ui.add(
+                    DragValue::new(&mut fluid.rest_density)
+                        .speed(0.01)
+                        .clamp_range(0.01..=f32::MAX),
+                );
+                ui.end_row();
+                ui.label("viscosity");
+                ui.add(
+                    DragValue::new(&mut fluid.viscosity)
+                        .speed(1.0e-4)
+                        .clamp_range(0.0..=f32::MAX),
+                );
+                ui.end_row();
+                ui.label("stiffness");
+                ui.add(
+                    DragValue::new(&mut fluid.stiffness)
+                        .speed(0.01)
+                        .clamp_range(0.01..=f32::MAX),
+                );
+                ui.end_row();
+            });
+            // * The solvers take their parameters at creation, so applying restarts the scene
+            if ui
+                .add_enabled(*fluid != applied, egui::Button::new("Restart with these"))
+                .clicked()
+            {
+                actions.push(UiAction::SetFluid(*fluid));
+            }
+        });
+    }
+
+    fn render_panel(ui: &mut egui::Ui, status: &Status, actions: &mut Vec<UiAction>) {
+        egui::CollapsingHeader::new("Render").show(ui, |ui| {
+            let mut config = status.config.clone();
+            let mut alpha = config.alpha();
+            let mut radius = config.radius();
+            let mut max_steps = config.max_steps();
+            let mut max_distance = config.max_distance();
+            let mut view = config.view();
+            let mut slice_depth = config.slice_depth();
+            let mut boundary = config.boundary();
+            let mut attribute = config.attribute();
+            let mut colormap = config.colormap();
+            let mut color_range = config.color_range();
+            let mut shading = config.shading().clone();
+            let mut changed = false;
+            egui::Grid::new("render").num_columns(2).show(ui, |ui| {
+                ui.label("alpha");
+                changed |= ui.add(DragValue::new(&mut alpha).speed(0.005)).changed();
+                ui.end_row();
+                ui.label("radius");
+                changed |= ui.add(DragValue::new(&mut radius).speed(0.05)).changed();
+                ui.end_row();
+                ui.label("max steps");
+                changed |= ui.add(DragValue::new(&mut max_steps)).changed();
+                ui.end_row();
+                ui.label("max distance");
+                changed |= ui
+                    .add(DragValue::new(&mut max_distance).speed(1.))
+                    .changed();
+                ui.end_row();
+                ui.label("view");
+                egui::ComboBox::from_id_source("view")
+                    .selected_text(view.name())
+                    .show_ui(ui, |ui| {
+                        for mode in ViewMode::ALL {
+                            changed |= ui.selectable_value(&mut view, mode, mode.name()).changed();
+                        }
+                    });
+                ui.end_row();
+                ui.label("ior");
+                changed |= ui
+                    .add(DragValue::new(&mut shading.ior).speed(0.01))
+                    .changed();
+                ui.end_row();
+                ui.label("exposure");
+                changed |= ui
+                    .add(DragValue::new(&mut shading.exposure).speed(0.01))
+                    .changed();
+                ui.end_row();
+                ui.label("shininess");
+                changed |= ui
+                    .add(DragValue::new(&mut shading.shininess).speed(1.))
+                    .changed();
+                ui.end_row();
+                ui.label("absorption");
+                ui.horizontal(|ui| {
+                    for coefficient in &mut shading.absorption {
+                        changed |= ui.add(DragValue::new(coefficient).speed(1.0e-3)).changed();
+                    }
+                });
+                ui.end_row();
+                ui.label("fluid colour");
+                changed |= ui.color_edit_button_rgb(&mut shading.fluid_color).changed();
+                ui.end_row();
+                ui.label("boundary");
+                egui::ComboBox::from_id_source("boundary")
+                    .selected_text(boundary.name())
+                    .show_ui(ui, |ui| {
+                        for mode in BoundaryMode::ALL {
+                            changed |= ui
+                                .selectable_value(&mut boundary, mode, mode.name())
+                                .changed();
+                        }
+                    });
+                ui.end_row();
+                if boundary != BoundaryMode::Blend {
+                    ui.label("boundary colour");
+                    changed |= ui
+                        .color_edit_button_rgb(&mut shading.boundary_color)
+                        .changed();
+                    ui.end_row();
+                }
+                if view == ViewMode::Attribute {
+                    ui.label("attribute");
+                    egui::ComboBox::from_id_source("attribute")
+                        .selected_text(attribute.name())
+                        .show_ui(ui, |ui| {
+                            for a in ParticleAttribute::ALL {
+                                changed |=
+                                    ui.selectable_value(&mut attribute, a, a.name()).changed();
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("colormap");
+                    egui::ComboBox::from_id_source("colormap")
+                        .selected_text(colormap.name())
+                        .show_ui(ui, |ui| {
+                            for c in Colormap::ALL {
+                                changed |=
+                                    ui.selectable_value(&mut colormap, c, c.name()).changed();
+                            }
+                        });
+                    ui.end_row();
+                    ui.label("range");
+                    ui.horizontal(|ui| {
+                        let mut fit = color_range.is_none();
+                        if ui.checkbox(&mut fit, "fit").changed() {
+                            // * Starts from the fitted range, so that nothing jumps
+                            color_range = (!fit).then(|| status.scalar_range.unwrap_or([0., 1.]));
+                            changed = true;
+                        }
+                        if let Some([min, max]) = &mut color_range {
+                            let speed = ((*max - *min).abs() * 1.0e-3).max(1.0e-4);
+                            changed |= ui.add(DragValue::new(min).speed(speed)).changed();
+                            changed |= ui.add(DragValue::new(max).speed(speed)).changed();
+                        }
+                    });
+                    ui.end_row();
+                    if let Some([min, max]) = status.scalar_range {
+                        ui.label("fluid");
+                        ui.label(format!("{min:.4} to {max:.4}"));
+                        ui.end_row();
+                    }
+                }
+                if view == ViewMode::Slice {
+                    ui.label("slice depth");
+                    changed |= ui.add(DragValue::new(&mut slice_depth).speed(1.)).changed();
+                    ui.end_row();
+                }
+            });
+            if changed {
+                config.set_alpha(alpha);
+                config.set_radius(radius);
+                config.set_max_steps(max_steps);
+                config.set_max_distance(max_distance);
+                config.set_view(view);
+                config.set_slice_depth(slice_depth);
+                config.set_boundary(boundary);
+                config.set_attribute(attribute);
+                config.set_colormap(colormap);
+                config.set_color_range(color_range);
+                config.set_shading(shading);
+                actions.push(UiAction::SetConfig(config));
+            }
+        });
+    }
+
+    fn performance_panel(&self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Performance")
+            .default_open(true)
+            .show(ui, |ui| {
+                let mean =
+                    self.frame_times.iter().sum::<f32>() / self.frame_times.len().max(1) as f32;
+                let fps = if mean > 0. { 1. / mean } else { 0. };
+                ui.label(format!("{fps:.0} fps, {:.2} ms", mean * 1000.));
+                let times: PlotPoints = self
+                    .frame_times
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &t)| [i as f64, t as f64 * 1000.])
+                    .collect();
+                Plot::new("frame times")
+                    .height(80.)
+                    .include_x(0.)
+                    .include_x(FRAME_HISTORY as f64)
+                    .include_y(0.)
+                    .allow_drag(false)
+                    .allow_zoom(false)
+                    .allow_scroll(false)
+                    .show_x(false)
+                    .show(ui, |plot| plot.line(Line::new(times).name("ms")));
+            });
+    }
+
+    fn camera_panel(ui: &mut egui::Ui, status: &Status, actions: &mut Vec<UiAction>) {
+        egui::CollapsingHeader::new("Camera").show(ui, |ui| {
+            let (pos, dir) = (status.camera_pos, status.camera_dir);
+            ui.label(format!("position: {:.2} {:.2} {:.2}", pos.x, pos.y, pos.z));
+            ui.label(format!("direction: {:.2} {:.2} {:.2}", dir.x, dir.y, dir.z));
+            ui.horizontal(|ui| {
+                for mode in [CameraMode::Fly, CameraMode::Orbit] {
+                    if ui
+                        .selectable_label(status.camera_mode == mode, mode.name())
+                        .clicked()
+                    {
+                        actions.push(UiAction::SetCameraMode(mode));
+                    }
+                }
+            });
+            if status.camera_mode == CameraMode::Orbit {
+                let target = status.camera_target;
+                ui.label(format!(
+                    "target: {:.2} {:.2} {:.2}",
+                    target.x, target.y, target.z
+                ));
+            }
+            let mut fov = status.camera_fov;
+            ui.horizontal(|ui| {
+                ui.label("fov");
+                if ui
+                    .add(DragValue::new(&mut fov).speed(0.5).clamp_range(1.0..=170.0))
+                    .changed()
+                {
+                    actions.push(UiAction::SetFov(fov));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label(format!("keyframes: {}", status.keyframes));
+                if ui.button("Add").clicked() {
+                    actions.push(UiAction::AddKeyframe);
+                }
+                let play = if status.playing_path { "Stop" } else { "Play" };
+                if ui
+                    .add_enabled(status.keyframes > 0, egui::Button::new(play))
+                    .clicked()
+                {
+                    actions.push(UiAction::SetPlayingPath(!status.playing_path));
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use glam::Vec3;
+
+// * One particle frame, in simulation units. Attributes the backend does not provide are None.
+pub struct VtkFrame<'a> {
+    pub time: f64,
+    pub positions: &'a [Vec3],
+    pub velocities: Option<&'a [Vec3]>,
+    pub densities: Option<&'a [f32]>,
+    pub pressures: Option<&'a [f32]>,
+    pub movable: &'a [bool],
+}
+
+// * Time series of VTU point clouds in a directory, indexed by a PVD collection for ParaView
+pub struct VtkSeries {
+    dir: PathBuf,
+    // * Export every this many steps, None for on demand only
+    every: Option<u32>,
+    steps: u32,
+    // * (time, file name) of the frames written so far
+    entries: Vec<(f64, String)>,
+}
+
+impl VtkSeries {
+    pub fn new(dir: &Path, every: Option<u32>) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("failed to create {}: {e}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            every,
+            steps: 0,
+            entries: vec![],
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn set_every(&mut self, every: Option<u32>) {
+        self.every = every;
+        self.steps = 0;
+    }
+
+    // * Counts a solver step, true if the frame after it should be exported
+    pub fn step(&mut self) -> bool {
+        let Some(every) = self.every else {
+            return false;
+        };
+        self.steps += 1;
+        if self.steps >= every {
+            self.steps = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // * Writes the frame and rewrites the collection, returns the path of the frame
+    pub fn write(&mut self, frame: &VtkFrame) -> Result<PathBuf, String> {
+        let name = format!("frame_{:05}.vtu", self.entries.len());
+        let path = self.dir.join(&name);
+        fs::write(&path, Self::vtu(frame))
+            .map_err(|e| format!("failed to write {}: {e}", path.display()))?;
+        self.entries.push((frame.time, name));
+
+        let collection = self.dir.join("particles.pvd");
+        fs::write(&collection, self.pvd())
+            .map_err(|e| format!("failed to write {}: {e}", collection.display()))?;
+        Ok(path)
+    }
+
+    fn pvd(&self) -> String {
+        let mut pvd = String::new();
+        pvd.push_str("<?xml version=\"1.0\"?>\n");
+        pvd.push_str("<VTKFile type=\"Collection\" version=\"0.1\" byte_order=\"LittleEndian\">\n");
+        pvd.push_str("  <Collection>\n");
+        for (time, name) in &self.entries {
+            let _ = writeln!(
+                pvd,
+                "    <DataSet timestep=\"{time}\" group=\"\" part=\"0\" file=\"{name}\"/>"
+            );
+        }
+        pvd.push_str("  </Collection>\n</VTKFile>\n");
+        pvd
+    }
+
+    // * ASCII unstructured grid with one vertex cell per particle
+    fn vtu(frame: &VtkFrame) -> String {
+        let n = frame.positions.len();
+        let mut vtu = String::new();
+        vtu.push_str("<?xml version=\"1.0\"?>\n");
+        vtu.push_str(
+            "<VTKFile type=\"UnstructuredGrid\" version=\"0.1\" byte_order=\"LittleEndian\">\n",
+        );
+        vtu.push_str("  <UnstructuredGrid>\n");
+        let _ = writeln!(
+            vtu,
+            "    <Piece NumberOfPoints=\"{n}\" NumberOfCells=\"{n}\">"
+        );
+
+        vtu.push_str("      <PointData>\n");
+        if let Some(velocities) = frame.velocities {
+            Self::data_array(&mut vtu, "Float32", "velocity", 3, vectors(velocities));
+        }
+        if let Some(densities) = frame.densities {
+            Self::data_array(&mut vtu, "Float32", "density", 1, densities.iter());
+        }
+        if let Some(pressures) = frame.pressures {
+            Self::data_array(&mut vtu, "Float32", "pressure", 1, pressures.iter());
+        }
+        let movable = frame.movable.iter().map(|&m| m as u8);
+        Self::data_array(&mut vtu, "UInt8", "movable", 1, movable);
+        vtu.push_str("      </PointData>\n");
+
+        vtu.push_str("      <Points>\n");
+        Self::data_array(&mut vtu, "Float32", "position", 3, vectors(frame.positions));
+        vtu.push_str("      </Points>\n");
+
+        vtu.push_str("      <Cells>\n");
+        Self::data_array(&mut vtu, "Int32", "connectivity", 1, 0..n);
+        Self::data_array(&mut vtu, "Int32", "offsets", 1, 1..=n);
+        // * VTK_VERTEX
+        let types = std::iter::repeat_n(1u8, n);
+        Self::data_array(&mut vtu, "UInt8", "types", 1, types);
+        vtu.push_str("      </Cells>\n");
+
+        vtu.push_str("    </Piece>\n  </UnstructuredGrid>\n</VTKFile>\n");
+        vtu
+    }
+
+    fn data_array<T: std::fmt::Display>(
+        vtu: &mut String,
+        ty: &str,
+        name: &str,
+        components: u32,
+        values: impl Iterator<Item = T>,
+    ) {
+        let _ = writeln!(
+            vtu,
+            "        <DataArray type=\"{ty}\" Name=\"{name}\" NumberOfComponents=\"{components}\" format=\"ascii\">"
+        );
+        vtu.push_str("         ");
+        for value in values {
+            let _ = write!(vtu, " {value}");
+        }
+        vtu.push_str("\n        </DataArray>\n");
+    }
+}
+
+fn vectors(values: &[Vec3]) -> impl Iterator<Item = f32> + '_ {
+    values.iter().flat_map(|v| v.to_array())
+}